@@ -0,0 +1,230 @@
+// Shared helpers for drawing one bubble per mesh triangle as an instanced quad.
+//
+// NOTE: expects the view and mesh bindings, a `Vertex` struct and the `vertex_buffer`
+// storage binding to be declared before this is imported. With `BUBBLE_METABALLS`, it also
+// expects a `bubble_radius_at(instance_index: u32) -> f32` function.
+
+// Which bubble each instance draws, back to front. See `sorting.rs`
+@group(3) @binding(5)
+var<storage> bubble_order: array<u32>;
+
+// The camera bubble radii are measured in, which is the view itself except in shadow maps.
+// See `view.rs`
+struct BubbleCamera {
+    view_proj: mat4x4<f32>,
+    // `projection[1][1]`, from half viewport heights to world units at a depth of 1
+    projection_scale: f32,
+};
+
+@group(3) @binding(6)
+var<uniform> bubble_camera: BubbleCamera;
+
+#ifdef BUBBLE_METABALLS
+// A grid of screen tiles, each listing the bubbles centered in it. See `metaballs.rs`
+struct BubbleBins {
+    dims: vec2<u32>,
+    // in NDC
+    tile_size: vec2<f32>,
+    blend_radius: f32,
+};
+
+@group(3) @binding(0)
+var<uniform> bubble_bins: BubbleBins;
+
+// (start, count) into `bubble_bin_entries`, for each tile
+@group(3) @binding(1)
+var<storage> bubble_bin_ranges: array<vec2<u32>>;
+
+// instance indices, sorted by tile
+@group(3) @binding(2)
+var<storage> bubble_bin_entries: array<u32>;
+#endif
+
+// The bubble to draw for the instance at `draw_index`. When the bubbles aren't sorted the
+// order only has the first one, and the rest are drawn in mesh order.
+fn bubble_instance(draw_index: u32) -> u32 {
+    if draw_index < arrayLength(&bubble_order) {
+        return bubble_order[draw_index];
+    }
+    return draw_index;
+}
+
+struct BubbleTriangle {
+    vert0: Vertex,
+    vert1: Vertex,
+    vert2: Vertex,
+    // whichever of the three verts this instance was drawn for
+    current_vert: Vertex,
+};
+
+fn bubble_triangle(instance_index: u32) -> BubbleTriangle {
+    var triangle: BubbleTriangle;
+
+    switch instance_index % 3u {
+        case 0u: {
+            triangle.vert0 = vertex_buffer[instance_index];
+            triangle.vert1 = vertex_buffer[instance_index + 1u];
+            triangle.vert2 = vertex_buffer[instance_index + 2u];
+
+            triangle.current_vert = triangle.vert0;
+        }
+        case 1u: {
+            triangle.vert0 = vertex_buffer[instance_index - 1u];
+            triangle.vert1 = vertex_buffer[instance_index];
+            triangle.vert2 = vertex_buffer[instance_index + 1u];
+
+            triangle.current_vert = triangle.vert1;
+        }
+        case 2u: {
+            triangle.vert0 = vertex_buffer[instance_index - 2u];
+            triangle.vert1 = vertex_buffer[instance_index - 1u];
+            triangle.vert2 = vertex_buffer[instance_index];
+
+            triangle.current_vert = triangle.vert2;
+        }
+        default: {
+            // definitely impossible, right??
+        }
+    }
+
+    return triangle;
+}
+
+fn bubble_centroid_world_position(triangle: BubbleTriangle) -> vec4<f32> {
+    var vert0_world = mesh_position_local_to_world(mesh.model, vec4(triangle.vert0.position, 1.0));
+    var vert1_world = mesh_position_local_to_world(mesh.model, vec4(triangle.vert1.position, 1.0));
+    var vert2_world = mesh_position_local_to_world(mesh.model, vec4(triangle.vert2.position, 1.0));
+
+    return (vert0_world + vert1_world + vert2_world) / 3.0;
+}
+
+// Place a corner of the bubble's quad (from -1 to 1 on each axis) around the bubble's center
+// on screen. `radius` is in half viewport heights, so the quad is narrowed by the aspect
+// ratio to stay square.
+fn bubble_quad_clip_position(
+    instance_index: u32,
+    corner: vec2<f32>,
+    sphere_center: vec4<f32>,
+    radius: f32,
+) -> vec4<f32> {
+    // behind the camera, so collapse the quad to nothing
+    if sphere_center.w <= 0.0 {
+        return vec4(0.0, 0.0, 0.0, 1.0);
+    }
+
+    var quad_radius = radius;
+#ifdef BUBBLE_METABALLS
+    // the other two verts of the triangle are in the same place, and would draw the same
+    // blob again
+    if instance_index % 3u != 0u {
+        return vec4(0.0, 0.0, 0.0, 1.0);
+    }
+    // leave room to blend with the neighbours
+    quad_radius += bubble_bins.blend_radius;
+#endif
+
+    var size = vec2(quad_radius * view.viewport.w / view.viewport.z, quad_radius);
+    // at the depth of the bubble's center, so it's hidden behind things in front of it and
+    // lands in the right place in shadow maps. Clamped like `DEPTH_CLAMP_ORTHO`, so bubbles
+    // between a directional light and its near plane still cast shadows
+    var depth = clamp(sphere_center.z / sphere_center.w, 0.0, 1.0);
+    return vec4(sphere_center.xy / sphere_center.w + corner * size, depth, 1.0);
+}
+
+// `radius` (in the bubble camera's half viewport heights) in this view's, so bubbles are the
+// same size in world space in every view. In the camera's own view it's left as it is.
+fn bubble_view_radius(
+    radius: f32,
+    centroid_world_position: vec4<f32>,
+    sphere_center: vec4<f32>,
+) -> f32 {
+    // behind the camera, where it isn't drawn either
+    var camera_w = max((bubble_camera.view_proj * centroid_world_position).w, 0.0);
+    var world_radius = radius * camera_w / bubble_camera.projection_scale;
+    return world_radius * view.projection[1][1] / sphere_center.w;
+}
+
+// Distance from the bubble's center to the ray through this fragment, in half viewport
+// heights. The rays are orthographic, so this is just the distance on screen.
+fn bubble_ray_distance(frag_coord: vec4<f32>, sphere_center: vec4<f32>) -> f32 {
+    // frag_coord is relative to the whole render target, which may hold several viewports
+    var viewport_uv = coords_to_viewport_uv(frag_coord.xy, view.viewport);
+    var frag_ndc = vec2(viewport_uv.x * 2.0 - 1.0, 1.0 - viewport_uv.y * 2.0);
+
+    var offset = frag_ndc - sphere_center.xy / sphere_center.w;
+    offset.x *= view.viewport.z / view.viewport.w;
+
+    return length(offset);
+}
+
+// The normal of the bubble's sphere at this fragment, in view space (so z points at the
+// camera). The rays are orthographic, so this only depends on where the fragment is on the
+// bubble's disk. Fragments outside of it (from metaballs) get the normal at the edge.
+fn bubble_sphere_normal(
+    frag_coord: vec4<f32>,
+    sphere_center: vec4<f32>,
+    radius: f32,
+) -> vec3<f32> {
+    var viewport_uv = coords_to_viewport_uv(frag_coord.xy, view.viewport);
+    var frag_ndc = vec2(viewport_uv.x * 2.0 - 1.0, 1.0 - viewport_uv.y * 2.0);
+
+    var offset = frag_ndc - sphere_center.xy / sphere_center.w;
+    offset.x *= view.viewport.z / view.viewport.w;
+
+    var disk = offset / radius;
+    disk /= max(length(disk), 1.0);
+    return vec3(disk, sqrt(max(1.0 - dot(disk, disk), 0.0)));
+}
+
+#ifdef BUBBLE_METABALLS
+// Polynomial smooth minimum: the same as min(a, b), except within `k` of each other
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    var h = max(k - abs(a - b), 0.0) / k;
+    return min(a, b) - h * h * k * 0.25;
+}
+
+fn bubble_center_clip_position(instance_index: u32) -> vec4<f32> {
+    var triangle = bubble_triangle(instance_index);
+    return mesh_position_world_to_clip(bubble_centroid_world_position(triangle));
+}
+
+// Whether the fragment is inside the blob the bubble at `instance_index` is part of, blended
+// with every bubble in the tiles around it. Only the bubble closest to the fragment says
+// yes, so the quads of overlapping bubbles don't draw the same blob twice.
+fn bubble_metaball_hit(frag_coord: vec4<f32>, instance_index: u32, sphere_center: vec4<f32>) -> bool {
+    var own = bubble_ray_distance(frag_coord, sphere_center) - bubble_radius_at(instance_index);
+    var field = own;
+    var k = max(bubble_bins.blend_radius, 0.0001);
+
+    var viewport_uv = coords_to_viewport_uv(frag_coord.xy, view.viewport);
+    var ndc = vec2(viewport_uv.x * 2.0 - 1.0, 1.0 - viewport_uv.y * 2.0);
+    var tile = vec2<i32>(floor((ndc + 1.0) / bubble_bins.tile_size));
+    var dims = vec2<i32>(bubble_bins.dims);
+
+    for (var y = tile.y - 1; y <= tile.y + 1; y += 1) {
+        for (var x = tile.x - 1; x <= tile.x + 1; x += 1) {
+            if x < 0 || y < 0 || x >= dims.x || y >= dims.y {
+                continue;
+            }
+
+            var range = bubble_bin_ranges[y * dims.x + x];
+            for (var i = range.x; i < range.x + range.y; i += 1u) {
+                var other = bubble_bin_entries[i];
+                if other == instance_index {
+                    continue;
+                }
+
+                var center = bubble_center_clip_position(other);
+                var dist = bubble_ray_distance(frag_coord, center) - bubble_radius_at(other);
+                // ties go to the first bubble
+                if dist < own || (dist == own && other < instance_index) {
+                    return false;
+                }
+                field = smooth_min(field, dist, k);
+            }
+        }
+    }
+
+    return field <= 0.0;
+}
+#endif
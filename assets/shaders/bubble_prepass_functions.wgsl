@@ -0,0 +1,84 @@
+// The `vertex` and `fragment` entry points for drawing bubbles into shadow maps and the
+// depth/normal prepasses, as the same spheres the bubbles pass draws. Only the shape matters
+// here, so none of the coloring is needed.
+//
+// NOTE: expects the prepass bindings, `bubble_functions.wgsl` and a
+// `bubble_radius_at(instance_index: u32) -> f32` function to be declared before this is
+// imported.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+
+    @location(0) centroid_clip_position: vec4<f32>,
+    @location(1) @interpolate(flat) instance_index: u32,
+    // in this view, see `bubble_view_radius`
+    @location(2) @interpolate(flat) radius: f32,
+};
+
+@vertex
+fn vertex(
+    @builtin(instance_index) draw_index: u32,
+    @location(0) quad_vert_position: vec3<f32>,
+) -> VertexOutput {
+    var out: VertexOutput;
+
+    var instance_index = bubble_instance(draw_index);
+
+    var triangle = bubble_triangle(instance_index);
+    var centroid_world_position = bubble_centroid_world_position(triangle);
+
+    out.centroid_clip_position = mesh_position_world_to_clip(centroid_world_position);
+    out.instance_index = instance_index;
+    // as big as the camera sees them, even from a light
+    out.radius = bubble_view_radius(
+        bubble_radius_at(instance_index),
+        centroid_world_position,
+        out.centroid_clip_position,
+    );
+    out.clip_position = bubble_quad_clip_position(
+        instance_index,
+        quad_vert_position.xy,
+        out.centroid_clip_position,
+        out.radius,
+    );
+
+    return out;
+}
+
+struct FragmentInput {
+    @builtin(position) frag_coord: vec4<f32>,
+
+    @location(0) centroid_clip_position: vec4<f32>,
+    @location(1) @interpolate(flat) instance_index: u32,
+    @location(2) @interpolate(flat) radius: f32,
+};
+
+// the same test as the end of the bubbles pass
+fn bubble_hit(in: FragmentInput) -> bool {
+#ifdef BUBBLE_METABALLS
+    // only in camera views, where the radius is the same as bubble_radius_at's
+    return bubble_metaball_hit(in.frag_coord, in.instance_index, in.centroid_clip_position);
+#else
+    return bubble_ray_distance(in.frag_coord, in.centroid_clip_position) <= in.radius;
+#endif
+}
+
+#ifdef NORMAL_PREPASS
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    if !bubble_hit(in) {
+        discard;
+    }
+
+    var normal = bubble_sphere_normal(in.frag_coord, in.centroid_clip_position, in.radius);
+    var world_normal = normalize((view.view * vec4(normal, 0.0)).xyz);
+    return vec4(world_normal * 0.5 + vec3(0.5), 1.0);
+}
+#else
+@fragment
+fn fragment(in: FragmentInput) {
+    if !bubble_hit(in) {
+        discard;
+    }
+}
+#endif
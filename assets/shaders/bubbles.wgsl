@@ -15,64 +15,141 @@ var<uniform> bubble_radius: f32;
 @group(1) @binding(101)
 var<storage> vertex_buffer: array<Vertex>;
 
+@group(1) @binding(102)
+var<uniform> emissive_color: vec4<f32>;
+
+@group(1) @binding(103)
+var<uniform> emissive_intensity: f32;
+
+@group(1) @binding(104)
+var<uniform> tint: vec4<f32>;
+
+@group(1) @binding(105)
+var<uniform> gradient_color: vec4<f32>;
+
+@group(1) @binding(106)
+var<uniform> gradient_heights: vec2<f32>;
+
+@group(1) @binding(107)
+var<storage> mesh_colors: array<vec4<f32>>;
+
+@group(1) @binding(108)
+var<uniform> ior: f32;
+
+@group(1) @binding(109)
+var<uniform> thin_film_thickness: f32;
+
+@group(1) @binding(110)
+var<uniform> thin_film_tint: vec4<f32>;
+
+#ifdef BUBBLE_SHADING_WATER
+// the view before anything transparent was drawn, see `screen_texture.rs`
+@group(3) @binding(3)
+var screen_texture: texture_2d<f32>;
+
+@group(3) @binding(4)
+var screen_sampler: sampler;
+#endif
+
+#import "shaders/bubble_functions.wgsl"
+
+fn bubble_radius_at(instance_index: u32) -> f32 {
+    return bubble_radius;
+}
+
+#ifdef BUBBLE_SHADING_WATER
+// Schlick's approximation of how much light reflects off water, rather than going through
+fn fresnel(cos_theta: f32) -> f32 {
+    var f0 = (1.0 - ior) / (1.0 + ior);
+    f0 *= f0;
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// The colors of light reflecting off a thin film, like on a soap bubble. Looking through the
+// film at a steeper angle is like looking through more of it, so the colors cycle towards
+// the edge of the bubble
+fn thin_film(cos_theta: f32) -> vec3<f32> {
+    var phase = thin_film_thickness / max(cos_theta, 0.05);
+    return 0.5 + 0.5 * cos(6.28318 * (phase + vec3(0.0, 0.33, 0.67)));
+}
+
+fn water_color(frag_coord: vec4<f32>, sphere_center: vec4<f32>) -> vec4<f32> {
+    var normal = bubble_sphere_normal(frag_coord, sphere_center, bubble_radius);
+
+    // bend the ray looking into the screen, and follow it about a bubble's radius further.
+    // Half viewport heights to pixels, with y pointing down
+    var refracted = refract(vec3(0.0, 0.0, -1.0), normal, 1.0 / ior);
+    var shift = vec2(refracted.x, -refracted.y) * bubble_radius * view.viewport.w * 0.5;
+
+    // the screen texture covers the whole render target, so stay inside this view
+    var size = vec2<f32>(textureDimensions(screen_texture));
+    var uv = clamp(
+        frag_coord.xy + shift,
+        view.viewport.xy,
+        view.viewport.xy + view.viewport.zw - 1.0,
+    ) / size;
+    var behind = textureSample(screen_texture, screen_sampler, uv).rgb;
+
+    var reflectance = fresnel(normal.z);
+    var reflection = thin_film(normal.z) * thin_film_tint.rgb;
+    return vec4(mix(behind, reflection, reflectance), 1.0);
+}
+#endif
+
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
 
     @location(0) uv: vec2<f32>,
     @location(1) centroid_world_position: vec4<f32>,
     @location(2) centroid_clip_position: vec4<f32>,
+    // the bubble's color, for the sources that don't need a texture
+    @location(3) color: vec4<f32>,
+    @location(4) @interpolate(flat) instance_index: u32,
 };
 
 
 @vertex
 fn vertex(
-    @builtin(instance_index) instance_index: u32,
+    @builtin(instance_index) draw_index: u32,
     @location(0) quad_vert_position: vec3<f32>,
 ) -> VertexOutput {
     var out: VertexOutput;
 
-    var current_vert: Vertex;
-    var vert0: Vertex;
-    var vert1: Vertex;
-    var vert2: Vertex;
-
-    // the quad verts are already positioned in clip space. EZPZ
-    out.clip_position = vec4(quad_vert_position, 1.0);
-
-    switch instance_index % 3u {
-        case 0u: {
-            vert0 = vertex_buffer[instance_index];
-            vert1 = vertex_buffer[instance_index + 1u];
-            vert2 = vertex_buffer[instance_index + 2u];
-
-            current_vert = vert0;
-        }
-        case 1u: {
-            vert0 = vertex_buffer[instance_index - 1u];
-            vert1 = vertex_buffer[instance_index];
-            vert2 = vertex_buffer[instance_index + 1u];
-
-            current_vert = vert1;
-        }
-        case 2u: {
-            vert0 = vertex_buffer[instance_index - 2u];
-            vert1 = vertex_buffer[instance_index - 1u];
-            vert2 = vertex_buffer[instance_index];
-
-            current_vert = vert2;
-        }
-        default: {
-            // definitely impossible, right??
-        }
-    }
+    var instance_index = bubble_instance(draw_index);
 
-    var vert0_world = mesh_position_local_to_world(mesh.model, vec4(vert0.position, 1.0)) / 2.0;
-    var vert1_world = mesh_position_local_to_world(mesh.model, vec4(vert1.position, 1.0)) / 2.0;
-    var vert2_world = mesh_position_local_to_world(mesh.model, vec4(vert2.position, 1.0)) / 2.0;
+    var triangle = bubble_triangle(instance_index);
 
-    out.centroid_world_position = (vert0_world + vert1_world + vert2_world) / 3.0;
+    out.centroid_world_position = bubble_centroid_world_position(triangle);
     out.centroid_clip_position = mesh_position_world_to_clip(out.centroid_world_position);
-    out.uv = current_vert.uv;
+    out.uv = triangle.current_vert.uv;
+    out.instance_index = instance_index;
+
+    out.color = vec4(1.0);
+#ifdef BUBBLE_COLOR_VERTEX_COLOR
+    // meshes without vertex colors get a single white one
+    out.color = mesh_colors[min(instance_index, arrayLength(&mesh_colors) - 1u)];
+#endif
+#ifdef BUBBLE_COLOR_TINT
+    out.color = tint;
+#endif
+#ifdef BUBBLE_COLOR_GRADIENT
+    var height = (
+        triangle.vert0.position.y + triangle.vert1.position.y + triangle.vert2.position.y
+    ) / 3.0;
+    var t = clamp(
+        (height - gradient_heights.x) / (gradient_heights.y - gradient_heights.x),
+        0.0,
+        1.0,
+    );
+    out.color = mix(tint, gradient_color, t);
+#endif
+
+    out.clip_position = bubble_quad_clip_position(
+        instance_index,
+        quad_vert_position.xy,
+        out.centroid_clip_position,
+        bubble_radius,
+    );
 
     return out;
 }
@@ -85,35 +162,42 @@ struct InterpolatedFragmentInput {
     @location(0) uv: vec2<f32>,
     @location(1) centroid_world_position: vec4<f32>,
     @location(2) centroid_clip_position: vec4<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) @interpolate(flat) instance_index: u32,
 };
 
 @fragment
 fn fragment(in: InterpolatedFragmentInput) -> @location(0) vec4<f32> {
     var output_color: vec4<f32>;
 
-    var sphere_center = in.centroid_clip_position;
-
-    var viewport_uv = coords_to_viewport_uv(in.frag_coord.xy, view.viewport);
-    viewport_uv *= 2.0;
-    viewport_uv -= 1.0;
-    // adjust for aspect ratio of the viewport, I guess? this seems to look more correct
-    viewport_uv.x /= view.viewport.w / view.viewport.z;
-
-    // orthographic projection. perspective would project from a single point as origin
-    var ray_origin = vec3(viewport_uv, 1.0);
-    var ray_direction = vec3(viewport_uv, 0.0) - ray_origin.xyz;
-
     // distance from the sphere center to the ray
-    var dist = length(cross(sphere_center.xyz - ray_origin.xyz, ray_direction.xyz)) / length(ray_direction.xyz);
-
-    // TODO: need to z-order the spheres somehow, maybe with a depth prepass or something?
+    var dist = bubble_ray_distance(in.frag_coord, in.centroid_clip_position);
 
+#ifdef BUBBLE_SHADING_WATER
+    // the color source isn't used, so don't sample its texture either
+    output_color = water_color(in.frag_coord, in.centroid_clip_position);
+#else
     // TODO PBR rendering. oof it's probably gonna be expensive
+    output_color = in.color;
+#ifdef BUBBLE_COLOR_BASE_COLOR
+    output_color = material.base_color * textureSample(base_color_texture, base_color_sampler, in.uv);
+#endif
+#ifdef BUBBLE_COLOR_EMISSIVE
     output_color = textureSample(emissive_texture, emissive_sampler, in.uv);
+#endif
+    // can go well above 1.0, for bloom
+    output_color = vec4(output_color.rgb * emissive_color.rgb * emissive_intensity, output_color.a);
+#endif
 
+#ifdef BUBBLE_METABALLS
+    if !bubble_metaball_hit(in.frag_coord, in.instance_index, in.centroid_clip_position) {
+        discard;
+    }
+#else
     if dist > bubble_radius {
         discard;
     }
+#endif
 
     return output_color;
 }
@@ -0,0 +1,28 @@
+// Bubbles for shadow maps and the depth/normal prepasses, as the same spheres `bubbles.wgsl`
+// draws. The entry points are in `bubble_prepass_functions.wgsl`.
+
+#import bevy_pbr::prepass_bindings
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+#import bevy_pbr::utils
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+};
+
+@group(1) @binding(100)
+var<uniform> bubble_radius: f32;
+
+@group(1) @binding(101)
+var<storage> vertex_buffer: array<Vertex>;
+
+#import "shaders/bubble_functions.wgsl"
+
+fn bubble_radius_at(instance_index: u32) -> f32 {
+    return bubble_radius;
+}
+
+#import "shaders/bubble_prepass_functions.wgsl"
@@ -0,0 +1,13 @@
+// Where each part of a mesh disappears as it dissolves. Split out of `noisy_functions.wgsl`
+// for shaders (like the teleport's bubbles prepass) that dissolve without wobbling, and so
+// don't have the bindings it needs.
+//
+// NOTE: expects `noise.wgsl` and a `noise_scale` uniform to be declared before this is
+// imported.
+
+// Where a point on the mesh sits in a dissolve, in [0, 1]. It should disappear once the
+// dissolve progress passes this value. This doesn't animate over time, so the dissolve
+// eats away at the mesh steadily instead of flickering.
+fn dissolve_threshold(local_position: vec3<f32>) -> f32 {
+    return clamp(0.5 + 0.5 * snoise(vec4(noise_scale * local_position, 0.0)), 0.0, 1.0);
+}
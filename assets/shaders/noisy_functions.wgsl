@@ -0,0 +1,68 @@
+// Shared helpers for wobbling a mesh along its normals with 4D simplex noise.
+//
+// NOTE: expects the view and mesh bindings, plus `noise_magnitude`, `noise_scale`,
+// `time_scale` and `time` uniforms, to be declared before this is imported. `time` stands in
+// for `globals.time`, which the prepass doesn't have, so the wobble matches in every pass.
+
+#import "shaders/noise.wgsl"
+#import "shaders/dissolve.wgsl"
+
+// Step (as a fraction of the noise feature size) used to estimate the
+// displaced surface's normal with finite differences.
+const NORMAL_EPSILON: f32 = 0.05;
+
+// The noise offset for a point on the mesh, before it's scaled or applied along the normal.
+fn noise_offset(local_position: vec3<f32>) -> f32 {
+    var noise_sample = vec4(noise_scale * local_position, time * time_scale);
+    // TODO: noise offset, or abs(snoise(...)) ? It might be nice to avoid pushing verts
+    // in away since this sometimes causes a weird overlap effect that doesn't look super pretty
+
+    // alternatively, we could probably do noise3() using UV + time, but this feels like more fun
+    return noise_magnitude * snoise(noise_sample);
+}
+
+// `model` is either the mesh's model matrix, or the skinned model matrix for this vertex
+fn displaced_world_position(
+    model: mat4x4<f32>,
+    local_position: vec3<f32>,
+    world_normal: vec3<f32>,
+) -> vec4<f32> {
+    var world_position = mesh_position_local_to_world(model, vec4<f32>(local_position, 1.0));
+    // TODO: random direction instead of normal? It actually looks decent like this already!
+    return world_position + vec4(noise_offset(local_position) * world_normal, 0.0);
+}
+
+fn displaced_world_normal(
+    model: mat4x4<f32>,
+    local_position: vec3<f32>,
+    local_normal: vec3<f32>,
+    world_position: vec4<f32>,
+    world_normal: vec3<f32>,
+) -> vec3<f32> {
+    // Build an arbitrary tangent frame around the normal, then sample the displacement a
+    // little way along each tangent direction to see how the surface bends around this vertex.
+    // We don't have the mesh's tangents here, but any frame works as long as it's orthonormal.
+    var up = select(vec3(0.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), abs(local_normal.y) > 0.99);
+    var local_tangent = normalize(cross(up, local_normal));
+    var local_bitangent = cross(local_normal, local_tangent);
+
+    var epsilon = NORMAL_EPSILON / max(noise_scale, 1.0);
+    var tangent_position = displaced_world_position(
+        model,
+        local_position + epsilon * local_tangent,
+        world_normal
+    );
+    var bitangent_position = displaced_world_position(
+        model,
+        local_position + epsilon * local_bitangent,
+        world_normal
+    );
+
+    var displaced_normal = normalize(cross(
+        tangent_position.xyz - world_position.xyz,
+        bitangent_position.xyz - world_position.xyz
+    ));
+
+    // negative scaling flips the handedness of the frame in world space
+    return displaced_normal * sign_determinant_model_3x3();
+}
@@ -0,0 +1,90 @@
+// The `vertex` and `fragment` entry points for drawing a wobbling, dissolving mesh into
+// shadow maps and the depth/normal prepasses. Mostly a copy of `bevy_pbr::prepass`, plus the
+// same displacement and dissolve as the main pass, so the shadow wobbles with the mesh and
+// only what's left of it casts one.
+//
+// NOTE: expects the prepass and mesh bindings, `noisy_functions.wgsl` and a
+// `dissolve_progress() -> f32` function to be declared before this is imported. The
+// material's `specialize` has to give the prepass normals too, see `specialize_prepass`.
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+#ifdef VERTEX_UVS
+    @location(1) uv: vec2<f32>,
+#endif
+    // always, not just for the normal prepass, since the wobble moves vertices along them
+    @location(2) normal: vec3<f32>,
+#ifdef SKINNED
+    @location(4) joint_indices: vec4<u32>,
+    @location(5) joint_weights: vec4<f32>,
+#endif
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) local_position: vec3<f32>,
+#ifdef NORMAL_PREPASS
+    @location(1) world_normal: vec3<f32>,
+#endif
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+
+#ifdef SKINNED
+    var model = skin_model(vertex.joint_indices, vertex.joint_weights);
+    var world_normal = skin_normals(model, vertex.normal);
+#else
+    var model = mesh.model;
+    var world_normal = mesh_normal_local_to_world(vertex.normal);
+#endif
+
+    var world_position = displaced_world_position(model, vertex.position, world_normal);
+
+    out.clip_position = mesh_position_world_to_clip(world_position);
+#ifdef DEPTH_CLAMP_ORTHO
+    out.clip_position.z = min(out.clip_position.z, 1.0);
+#endif
+
+    out.local_position = vertex.position;
+
+#ifdef NORMAL_PREPASS
+    out.world_normal = displaced_world_normal(
+        model,
+        vertex.position,
+        vertex.normal,
+        world_position,
+        world_normal
+    );
+#endif
+
+    return out;
+}
+
+struct FragmentInput {
+    @location(0) local_position: vec3<f32>,
+#ifdef NORMAL_PREPASS
+    @location(1) world_normal: vec3<f32>,
+#endif
+};
+
+// Only the normal prepass has a target to write to, shadows and the depth prepass just need
+// the discard
+#ifdef NORMAL_PREPASS
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    if dissolve_threshold(in.local_position) < dissolve_progress() {
+        discard;
+    }
+
+    return vec4(in.world_normal * 0.5 + vec3(0.5), 1.0);
+}
+#else
+@fragment
+fn fragment(in: FragmentInput) {
+    if dissolve_threshold(in.local_position) < dissolve_progress() {
+        discard;
+    }
+}
+#endif
@@ -1,13 +1,10 @@
 // mostly a copy of `bevy_pbr::mesh`
 
-#import bevy_pbr::mesh_view_bindings
-#import bevy_pbr::mesh_bindings
+#import bevy_pbr::pbr_fragment
 
 // NOTE: Bindings must come before functions that use them!
 #import bevy_pbr::mesh_functions
 
-#import "shaders/noise.wgsl"
-
 @group(1) @binding(100)
 var<uniform> noise_magnitude: f32;
 
@@ -18,15 +15,35 @@ var<uniform> noise_scale: f32;
 @group(1) @binding(102)
 var<uniform> time_scale: f32;
 
+@group(1) @binding(103)
+var<uniform> dissolve: f32;
+
+@group(1) @binding(104)
+var<uniform> dissolve_edge_width: f32;
+
+@group(1) @binding(105)
+var<uniform> dissolve_edge_color: vec4<f32>;
+
+@group(1) @binding(106)
+var<uniform> time: f32;
+
+#import "shaders/noisy_functions.wgsl"
+#import "shaders/standard_lighting.wgsl"
+
 struct Vertex {
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) uv: vec2<f32>,
+#ifdef SKINNED
+    @location(5) joint_indices: vec4<u32>,
+    @location(6) joint_weights: vec4<f32>,
+#endif
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     #import bevy_pbr::mesh_vertex_output
+    @location(5) local_position: vec3<f32>,
 };
 
 
@@ -34,24 +51,63 @@ struct VertexOutput {
 fn vertex(vertex: Vertex) -> VertexOutput {
     var out: VertexOutput;
 
+#ifdef SKINNED
+    var model = skin_model(vertex.joint_indices, vertex.joint_weights);
+    var world_normal = skin_normals(model, vertex.normal);
+#else
+    var model = mesh.model;
     var world_normal = mesh_normal_local_to_world(vertex.normal);
+#endif
 
-    var world_position = mesh_position_local_to_world(mesh.model, vec4<f32>(vertex.position, 1.0));
+    var world_position = displaced_world_position(model, vertex.position, world_normal);
 
-    var noise_sample = vec4(noise_scale * vertex.position.xyz, globals.time * time_scale);
-    // TODO: noise offset, or abs(snoise(...)) ? It might be nice to avoid pushing verts
-    // in away since this sometimes causes a weird overlap effect that doesn't look super pretty
-
-    // alternatively, we could probably do noise3() using UV + time, but this feels like more fun
-    var offset = noise_magnitude * snoise(noise_sample);
-    // TODO: random direction instead of normal? It actually looks decent like this already!
-    world_position += vec4(offset * world_normal, 0.0);
+    var displaced_normal = displaced_world_normal(
+        model,
+        vertex.position,
+        vertex.normal,
+        world_position,
+        world_normal
+    );
 
     out.world_position = world_position;
-    out.world_normal = world_normal;
+    out.world_normal = displaced_normal;
     out.clip_position = mesh_position_world_to_clip(world_position);
 
     out.uv = vertex.uv;
+    out.local_position = vertex.position;
 
     return out;
 }
+
+struct NoisyFragmentInput {
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
+    #import bevy_pbr::mesh_vertex_output
+    @location(5) local_position: vec3<f32>,
+};
+
+@fragment
+fn fragment(in: NoisyFragmentInput) -> @location(0) vec4<f32> {
+    var threshold = dissolve_threshold(in.local_position);
+
+    // glow brightest right at the edge of the dissolve, fading out further away from it.
+    // Nothing should glow before the dissolve has started though.
+    var edge = 1.0 - smoothstep(dissolve, dissolve + dissolve_edge_width, threshold);
+    edge = select(0.0, edge, dissolve > 0.0);
+
+    // textures must be sampled in uniform control flow, so do this before discarding
+    var output_color = standard_lighting(
+        in.is_front,
+        in.frag_coord,
+        in.world_position,
+        in.world_normal,
+        in.uv,
+        edge * dissolve_edge_color.rgb,
+    );
+
+    if threshold < dissolve {
+        discard;
+    }
+
+    return output_color;
+}
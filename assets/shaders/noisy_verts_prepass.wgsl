@@ -0,0 +1,29 @@
+// `noisy_verts.wgsl` for shadow maps and the depth/normal prepasses. The entry points are in
+// `noisy_prepass_functions.wgsl`.
+
+#import bevy_pbr::prepass_bindings
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+
+@group(1) @binding(100)
+var<uniform> noise_magnitude: f32;
+
+@group(1) @binding(101)
+var<uniform> noise_scale: f32;
+
+@group(1) @binding(102)
+var<uniform> time_scale: f32;
+
+@group(1) @binding(103)
+var<uniform> dissolve: f32;
+
+@group(1) @binding(106)
+var<uniform> time: f32;
+
+fn dissolve_progress() -> f32 {
+    return dissolve;
+}
+
+#import "shaders/noisy_functions.wgsl"
+#import "shaders/noisy_prepass_functions.wgsl"
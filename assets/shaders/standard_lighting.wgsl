@@ -0,0 +1,95 @@
+// Mostly a copy of the body of the `bevy_pbr::pbr` fragment shader, so that custom fragment
+// shaders can light a `StandardMaterial` without reimplementing all of it. Normal maps and
+// vertex colors aren't supported, since our meshes don't use them.
+//
+// NOTE: expects `bevy_pbr::pbr_fragment` to already be imported.
+
+fn standard_lighting(
+    is_front: bool,
+    frag_coord: vec4<f32>,
+    world_position: vec4<f32>,
+    world_normal: vec3<f32>,
+    uv: vec2<f32>,
+    // added on top of the material's own emissive, e.g. for glowing effects
+    extra_emissive: vec3<f32>,
+) -> vec4<f32> {
+    var output_color: vec4<f32> = material.base_color;
+    if ((material.flags & STANDARD_MATERIAL_FLAGS_BASE_COLOR_TEXTURE_BIT) != 0u) {
+        output_color = output_color * textureSample(base_color_texture, base_color_sampler, uv);
+    }
+
+    // NOTE: Unlit bit not set means == 0 is true, so the true case is if lit
+    if ((material.flags & STANDARD_MATERIAL_FLAGS_UNLIT_BIT) == 0u) {
+        var pbr_input: PbrInput = pbr_input_new();
+
+        pbr_input.material.base_color = output_color;
+        pbr_input.material.reflectance = material.reflectance;
+        pbr_input.material.flags = material.flags;
+        pbr_input.material.alpha_cutoff = material.alpha_cutoff;
+
+        var emissive: vec4<f32> = material.emissive;
+        if ((material.flags & STANDARD_MATERIAL_FLAGS_EMISSIVE_TEXTURE_BIT) != 0u) {
+            emissive = vec4<f32>(emissive.rgb * textureSample(emissive_texture, emissive_sampler, uv).rgb, 1.0);
+        }
+        pbr_input.material.emissive = vec4(emissive.rgb + extra_emissive, emissive.a);
+
+        var metallic: f32 = material.metallic;
+        var perceptual_roughness: f32 = material.perceptual_roughness;
+        if ((material.flags & STANDARD_MATERIAL_FLAGS_METALLIC_ROUGHNESS_TEXTURE_BIT) != 0u) {
+            let metallic_roughness = textureSample(metallic_roughness_texture, metallic_roughness_sampler, uv);
+            // Sampling from GLTF standard channels for now
+            metallic = metallic * metallic_roughness.b;
+            perceptual_roughness = perceptual_roughness * metallic_roughness.g;
+        }
+        pbr_input.material.metallic = metallic;
+        pbr_input.material.perceptual_roughness = perceptual_roughness;
+
+        var occlusion: f32 = 1.0;
+        if ((material.flags & STANDARD_MATERIAL_FLAGS_OCCLUSION_TEXTURE_BIT) != 0u) {
+            occlusion = textureSample(occlusion_texture, occlusion_sampler, uv).r;
+        }
+        pbr_input.frag_coord = frag_coord;
+        pbr_input.world_position = world_position;
+        pbr_input.world_normal = prepare_world_normal(
+            world_normal,
+            (material.flags & STANDARD_MATERIAL_FLAGS_DOUBLE_SIDED_BIT) != 0u,
+            is_front,
+        );
+
+        pbr_input.is_orthographic = view.projection[3].w == 1.0;
+
+        // no normal mapping, so the lighting normal is just the geometric one
+        pbr_input.N = pbr_input.world_normal;
+        pbr_input.V = calculate_view(world_position, pbr_input.is_orthographic);
+        pbr_input.occlusion = occlusion;
+
+        pbr_input.flags = mesh.flags;
+
+        output_color = pbr(pbr_input);
+    } else {
+        output_color = alpha_discard(material, output_color);
+        output_color = vec4(output_color.rgb + extra_emissive, output_color.a);
+    }
+
+    // fog
+    if (fog.mode != FOG_MODE_OFF && (material.flags & STANDARD_MATERIAL_FLAGS_FOG_ENABLED_BIT) != 0u) {
+        output_color = apply_fog(output_color, world_position.xyz, view.world_position.xyz);
+    }
+
+#ifdef TONEMAP_IN_SHADER
+    output_color = tone_mapping(output_color);
+#ifdef DEBAND_DITHER
+    var output_rgb = output_color.rgb;
+    output_rgb = powsafe(output_rgb, 1.0 / 2.2);
+    output_rgb = output_rgb + screen_space_dither(frag_coord.xy);
+    // This conversion back to linear space is required because our output texture format is
+    // SRGB; the GPU will assume our output is linear and will apply an SRGB conversion.
+    output_rgb = powsafe(output_rgb, 2.2);
+    output_color = vec4(output_rgb, output_color.a);
+#endif
+#endif
+#ifdef PREMULTIPLY_ALPHA
+    output_color = premultiply_alpha(material.flags, output_color);
+#endif
+    return output_color;
+}
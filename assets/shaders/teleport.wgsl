@@ -0,0 +1,191 @@
+// Crossfade from the noisy mesh into bubbles. The mesh itself is drawn with `vertex` and
+// `fragment`, and dissolves away as `progress` goes from 0 to 1. The bubble pass uses
+// `bubble_vertex` and `bubble_fragment`, and grows a bubble from each triangle as soon as
+// the mesh around it has dissolved. The bubbles' shadows and prepasses are in
+// `teleport_prepass.wgsl`, and the mesh's are in `teleport_mesh_prepass.wgsl`.
+
+#import bevy_pbr::pbr_fragment
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+};
+
+@group(1) @binding(100)
+var<uniform> bubble_radius: f32;
+
+@group(1) @binding(101)
+var<storage> vertex_buffer: array<Vertex>;
+
+@group(1) @binding(102)
+var<uniform> noise_magnitude: f32;
+
+@group(1) @binding(103)
+var<uniform> noise_scale: f32;
+
+@group(1) @binding(104)
+var<uniform> time_scale: f32;
+
+@group(1) @binding(105)
+var<uniform> progress: f32;
+
+@group(1) @binding(106)
+var<uniform> emissive_color: vec4<f32>;
+
+@group(1) @binding(107)
+var<uniform> emissive_intensity: f32;
+
+@group(1) @binding(108)
+var<uniform> time: f32;
+
+#import "shaders/noisy_functions.wgsl"
+#import "shaders/bubble_functions.wgsl"
+#import "shaders/teleport_functions.wgsl"
+#import "shaders/standard_lighting.wgsl"
+
+// Vertex attributes for drawing the mesh itself. `Vertex` is used for the storage buffer,
+// which never has joints since bubbles are skinned on the CPU ahead of time.
+struct MeshVertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+#ifdef SKINNED
+    @location(5) joint_indices: vec4<u32>,
+    @location(6) joint_weights: vec4<f32>,
+#endif
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    #import bevy_pbr::mesh_vertex_output
+    @location(5) local_position: vec3<f32>,
+};
+
+@vertex
+fn vertex(vertex: MeshVertex) -> VertexOutput {
+    var out: VertexOutput;
+
+#ifdef SKINNED
+    var model = skin_model(vertex.joint_indices, vertex.joint_weights);
+    var world_normal = skin_normals(model, vertex.normal);
+#else
+    var model = mesh.model;
+    var world_normal = mesh_normal_local_to_world(vertex.normal);
+#endif
+
+    var world_position = displaced_world_position(model, vertex.position, world_normal);
+
+    out.world_position = world_position;
+    out.world_normal = displaced_world_normal(
+        model,
+        vertex.position,
+        vertex.normal,
+        world_position,
+        world_normal
+    );
+    out.clip_position = mesh_position_world_to_clip(world_position);
+
+    out.uv = vertex.uv;
+    out.local_position = vertex.position;
+
+    return out;
+}
+
+struct MeshFragmentInput {
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
+    #import bevy_pbr::mesh_vertex_output
+    @location(5) local_position: vec3<f32>,
+};
+
+@fragment
+fn fragment(in: MeshFragmentInput) -> @location(0) vec4<f32> {
+    // textures must be sampled in uniform control flow, so do this before discarding
+    var output_color = standard_lighting(
+        in.is_front,
+        in.frag_coord,
+        in.world_position,
+        in.world_normal,
+        in.uv,
+        vec3(0.0),
+    );
+
+    if dissolve_threshold(in.local_position) < progress {
+        discard;
+    }
+
+    return output_color;
+}
+
+struct BubbleVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+
+    @location(0) uv: vec2<f32>,
+    @location(1) centroid_world_position: vec4<f32>,
+    @location(2) centroid_clip_position: vec4<f32>,
+    @location(3) growth: f32,
+    @location(4) @interpolate(flat) instance_index: u32,
+};
+
+@vertex
+fn bubble_vertex(
+    @builtin(instance_index) draw_index: u32,
+    @location(0) quad_vert_position: vec3<f32>,
+) -> BubbleVertexOutput {
+    var out: BubbleVertexOutput;
+
+    var instance_index = bubble_instance(draw_index);
+
+    var triangle = bubble_triangle(instance_index);
+
+    out.centroid_world_position = bubble_centroid_world_position(triangle);
+    out.centroid_clip_position = mesh_position_world_to_clip(out.centroid_world_position);
+    out.uv = triangle.current_vert.uv;
+    out.growth = bubble_growth(triangle);
+    out.instance_index = instance_index;
+
+    out.clip_position = bubble_quad_clip_position(
+        instance_index,
+        quad_vert_position.xy,
+        out.centroid_clip_position,
+        bubble_radius * out.growth,
+    );
+
+    return out;
+}
+
+struct BubbleFragmentInput {
+    @builtin(position) frag_coord: vec4<f32>,
+
+    @location(0) uv: vec2<f32>,
+    @location(1) centroid_world_position: vec4<f32>,
+    @location(2) centroid_clip_position: vec4<f32>,
+    @location(3) growth: f32,
+    @location(4) @interpolate(flat) instance_index: u32,
+};
+
+@fragment
+fn bubble_fragment(in: BubbleFragmentInput) -> @location(0) vec4<f32> {
+    var dist = bubble_ray_distance(in.frag_coord, in.centroid_clip_position);
+
+    var output_color = textureSample(emissive_texture, emissive_sampler, in.uv);
+    // can go well above 1.0, for bloom
+    output_color = vec4(output_color.rgb * emissive_color.rgb * emissive_intensity, output_color.a);
+    output_color.a *= in.growth;
+
+#ifdef BUBBLE_METABALLS
+    if !bubble_metaball_hit(in.frag_coord, in.instance_index, in.centroid_clip_position) {
+        discard;
+    }
+#else
+    if dist > bubble_radius * in.growth {
+        discard;
+    }
+#endif
+
+    return output_color;
+}
@@ -0,0 +1,23 @@
+// How the teleport's bubbles grow, shared by `teleport.wgsl` and `teleport_prepass.wgsl` so
+// their shadows grow with them.
+//
+// NOTE: expects `bubble_functions.wgsl`, `dissolve.wgsl` and the `bubble_radius` and
+// `progress` uniforms to be declared before this is imported.
+
+// How far past its dissolve threshold `progress` has to go before a bubble is fully grown
+const BUBBLE_GROWTH_WIDTH: f32 = 0.1;
+
+// 0 until the mesh has dissolved around the bubble, then up to 1 when it's fully grown
+fn bubble_growth(triangle: BubbleTriangle) -> f32 {
+    // use the same threshold the mesh dissolves with, so each bubble appears right where
+    // its triangle disappeared
+    var centroid_local_position = (
+        triangle.vert0.position + triangle.vert1.position + triangle.vert2.position
+    ) / 3.0;
+    var threshold = dissolve_threshold(centroid_local_position);
+    return smoothstep(threshold, threshold + BUBBLE_GROWTH_WIDTH, progress);
+}
+
+fn bubble_radius_at(instance_index: u32) -> f32 {
+    return bubble_radius * bubble_growth(bubble_triangle(instance_index));
+}
@@ -0,0 +1,30 @@
+// The teleport's mesh for shadow maps and the depth/normal prepasses, wobbling and dissolving
+// just like `teleport.wgsl` draws it. Its bubbles are in `teleport_prepass.wgsl` instead. The
+// entry points are in `noisy_prepass_functions.wgsl`.
+
+#import bevy_pbr::prepass_bindings
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+
+@group(1) @binding(102)
+var<uniform> noise_magnitude: f32;
+
+@group(1) @binding(103)
+var<uniform> noise_scale: f32;
+
+@group(1) @binding(104)
+var<uniform> time_scale: f32;
+
+@group(1) @binding(105)
+var<uniform> progress: f32;
+
+@group(1) @binding(108)
+var<uniform> time: f32;
+
+fn dissolve_progress() -> f32 {
+    return progress;
+}
+
+#import "shaders/noisy_functions.wgsl"
+#import "shaders/noisy_prepass_functions.wgsl"
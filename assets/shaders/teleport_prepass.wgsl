@@ -0,0 +1,33 @@
+// The teleport's bubbles for shadow maps and the depth/normal prepasses, growing just like
+// the ones `teleport.wgsl` draws. The entry points are in `bubble_prepass_functions.wgsl`.
+
+#import bevy_pbr::prepass_bindings
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+#import bevy_pbr::utils
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+};
+
+@group(1) @binding(100)
+var<uniform> bubble_radius: f32;
+
+@group(1) @binding(101)
+var<storage> vertex_buffer: array<Vertex>;
+
+@group(1) @binding(103)
+var<uniform> noise_scale: f32;
+
+@group(1) @binding(105)
+var<uniform> progress: f32;
+
+// not noisy_functions.wgsl, since the bubbles don't wobble
+#import "shaders/noise.wgsl"
+#import "shaders/dissolve.wgsl"
+#import "shaders/bubble_functions.wgsl"
+#import "shaders/teleport_functions.wgsl"
+#import "shaders/bubble_prepass_functions.wgsl"
@@ -1,32 +1,46 @@
-use std::mem;
-use std::sync::Once;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
-use bevy::core::{Pod, Zeroable};
 use bevy::core_pipeline::core_3d::Transparent3d;
-use bevy::log;
+use bevy::core_pipeline::prepass::AlphaMask3dPrepass;
 use bevy::pbr::{
     extract_materials, prepare_materials, queue_material_meshes, ExtendedMaterial,
-    ExtractedMaterials, MaterialPipeline, MaterialPipelineKey, RenderMaterials,
+    ExtractedMaterials, MaterialPipeline, MaterialPipelineKey, RenderMaterials, Shadow,
 };
 use bevy::prelude::*;
 use bevy::reflect::TypeUuid;
 use bevy::render::extract_component::ExtractComponentPlugin;
 use bevy::render::mesh::MeshVertexBufferLayout;
-use bevy::render::render_asset::{prepare_assets, PrepareAssetSet, RenderAsset};
+use bevy::render::render_asset::PrepareAssetSet;
 use bevy::render::render_phase::AddRenderCommand;
 use bevy::render::render_resource::{
-    AsBindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
-    BufferInitDescriptor, BufferUsages, OwnedBindingResource, RenderPipelineDescriptor, ShaderRef,
-    ShaderType, SpecializedMeshPipelineError, SpecializedMeshPipelines, VertexAttribute,
-    VertexBufferLayout, VertexFormat, VertexStepMode,
+    AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+    SpecializedMeshPipelines,
 };
-use bevy::render::renderer::RenderDevice;
-use bevy::render::{Extract, RenderApp, RenderSet};
-use bevy::utils::{HashMap, HashSet};
+use bevy::render::{RenderApp, RenderSet};
 
-use self::pipeline::{queue_draw_bubbles, DrawCustom};
+use self::metaballs::{extract_bubble_radii, prepare_metaball_bins};
+use self::pipeline::{queue_draw_bubbles, BubbleQuad, BubblesPipeline, DrawCustom};
+use self::prepass::{
+    queue_bubble_shadows, queue_bubbles_prepass, BubblesPrepassPipeline, DrawBubblesPrepass,
+};
+use self::screen_texture::{extract_screen_texture_readers, ScreenTexturePlugin};
+use self::sorting::prepare_bubble_order;
+use self::view::{
+    prepare_bubble_cameras, queue_bubbles_view_bind_groups, BubblesViewLayout, ShadowBubbleCamera,
+};
+use crate::mesh_storage::{pose_meshes, MeshStorage, MeshStoragePlugin, Vertex};
 
+mod metaballs;
 mod pipeline;
+mod prepass;
+#[cfg(test)]
+mod reference;
+mod screen_texture;
+mod sorting;
+mod view;
 
 pub struct BubblesMaterialPlugin;
 
@@ -41,32 +55,148 @@ impl Plugin for BubblesMaterialPlugin {
             .init_resource::<MaterialPipeline<BubblesMaterial>>()
             .init_resource::<ExtractedMaterials<BubblesMaterial>>()
             .init_resource::<RenderMaterials<BubblesMaterial>>()
-            .init_resource::<ExtractedMeshes>()
-            .init_resource::<SpecializedMeshPipelines<MaterialPipeline<BubblesMaterial>>>()
             .add_system_to_schedule(ExtractSchedule, extract_materials::<BubblesMaterial>)
-            .add_system_to_schedule(ExtractSchedule, extract_meshes)
             .add_system(
                 prepare_materials::<BubblesMaterial>
                     .in_set(RenderSet::Prepare)
                     .after(PrepareAssetSet::PreAssetPrepare),
+            );
+
+        app.add_plugin(BubblesPassPlugin::<BubblesMaterial>::default());
+    }
+}
+
+/// A material which can be drawn as a cloud of bubbles, with one instance per vertex of
+/// the mesh it's attached to.
+///
+/// The mesh's vertices are read through [`MeshStorage`], so the material's bind group must
+/// have a storage binding of [`Vertex`] at `VERTEX_BINDING`. The bubbles are drawn with their
+/// own pipeline, so materials which are also drawn normally (through a [`MaterialPlugin`])
+/// can pick separate shader entry points for each.
+pub trait BubblesPass: MeshStorage {
+    /// The vertex shader entry point used to draw bubbles.
+    const VERTEX_ENTRY_POINT: &'static str = "vertex";
+
+    /// The fragment shader entry point used to draw bubbles.
+    const FRAGMENT_ENTRY_POINT: &'static str = "fragment";
+
+    /// The shader used to draw bubbles into shadow maps and the depth/normal prepasses, with
+    /// `vertex` and `fragment` entry points. By default there isn't one, and the bubbles
+    /// neither cast shadows nor show up in the prepasses.
+    fn prepass_shader() -> ShaderRef {
+        ShaderRef::Default
+    }
+
+    /// The biggest radius any bubble drawn with this material can have. Metaballs use this
+    /// to find which bubbles are close enough to merge.
+    fn bubble_radius(&self) -> f32;
+
+    /// Whether the shader samples the screen texture at `@group(3) @binding(3)`, a copy of
+    /// the view's opaque and alpha masked things. It's only copied while some material needs
+    /// it.
+    fn reads_screen_texture(&self) -> bool {
+        false
+    }
+}
+
+/// Draws every entity with a `Handle<M>` as bubbles, in the transparent pass (and the shadow
+/// and prepasses, with a [`BubblesPass::prepass_shader`]). This does not set up `M` itself as
+/// a material, that's up to [`BubblesMaterialPlugin`] or a [`MaterialPlugin`].
+pub struct BubblesPassPlugin<M>(PhantomData<M>);
+
+impl<M> Default for BubblesPassPlugin<M> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<M: BubblesPass> Plugin for BubblesPassPlugin<M>
+where
+    M::Data: PartialEq + Eq + Hash + Clone,
+{
+    fn build(&self, app: &mut App) {
+        // the vertex storage buffer is what the bubbles are drawn from
+        if !app.is_plugin_added::<MeshStoragePlugin<M>>() {
+            app.add_plugin(MeshStoragePlugin::<M>::default());
+        }
+        if !app.is_plugin_added::<ScreenTexturePlugin>() {
+            app.add_plugin(ScreenTexturePlugin);
+        }
+
+        // stats are shared by every material, and written from the render world
+        if !app.world.contains_resource::<BubblesStats>() {
+            let stats = BubblesStats::default();
+            app.insert_resource(stats.clone());
+            app.sub_app_mut(RenderApp).insert_resource(stats);
+        }
+
+        let render_app = app.sub_app_mut(RenderApp);
+
+        // the quad they're drawn on is shared by every bubbles material
+        if !render_app.world.contains_resource::<BubbleQuad>() {
+            render_app.init_resource::<BubbleQuad>();
+        }
+
+        // and so is the layout of the per-view bindings, and the camera they're sized for
+        if !render_app.world.contains_resource::<BubblesViewLayout>() {
+            render_app
+                .init_resource::<BubblesViewLayout>()
+                .init_resource::<ShadowBubbleCamera>()
+                .add_system(prepare_bubble_cameras.in_set(RenderSet::Prepare));
+        }
+
+        render_app
+            .add_system_to_schedule(ExtractSchedule, extract_bubble_radii::<M>)
+            .add_system_to_schedule(ExtractSchedule, extract_screen_texture_readers::<M>)
+            .init_resource::<BubblesPipeline<M>>()
+            .init_resource::<SpecializedMeshPipelines<BubblesPipeline<M>>>()
+            .init_resource::<BubblesPrepassPipeline<M>>()
+            .init_resource::<SpecializedMeshPipelines<BubblesPrepassPipeline<M>>>()
+            .add_render_command::<Transparent3d, DrawCustom<M>>()
+            .add_render_command::<AlphaMask3dPrepass, DrawBubblesPrepass<M>>()
+            .add_render_command::<Shadow, DrawBubblesPrepass<M>>()
+            .add_system(
+                prepare_metaball_bins::<M>
+                    .in_set(RenderSet::Prepare)
+                    .after(pose_meshes),
             )
-            .add_render_command::<Transparent3d, DrawCustom>()
             .add_system(
-                prepare_bubble_material
+                prepare_bubble_order::<M>
                     .in_set(RenderSet::Prepare)
-                    .after(prepare_materials::<BubblesMaterial>)
-                    .after(prepare_assets::<Mesh>),
+                    .after(pose_meshes),
             )
+            .add_system(queue_bubbles_view_bind_groups::<M>.in_set(RenderSet::Queue))
             .add_system(
-                queue_draw_bubbles
+                queue_draw_bubbles::<M>
                     .in_set(RenderSet::Queue)
-                    .after(queue_material_meshes::<BubblesMaterial>),
-            );
+                    .after(queue_material_meshes::<M>),
+            )
+            .add_system(queue_bubbles_prepass::<M>.in_set(RenderSet::Queue))
+            .add_system(queue_bubble_shadows::<M>.in_set(RenderSet::Queue));
     }
 }
 
 pub type BubblesMaterial = ExtendedMaterial<Bubbles>;
 
+impl MeshStorage for BubblesMaterial {
+    const VERTEX_BINDING: u32 = 101;
+    const COLOR_BINDING: Option<u32> = Some(107);
+}
+
+impl BubblesPass for BubblesMaterial {
+    fn bubble_radius(&self) -> f32 {
+        self.extended.bubble_radius
+    }
+
+    fn prepass_shader() -> ShaderRef {
+        "shaders/bubbles_prepass.wgsl".into()
+    }
+
+    fn reads_screen_texture(&self) -> bool {
+        self.extended.shading == BubbleShading::Water
+    }
+}
+
 pub fn material_from_standard(standard: StandardMaterial) -> BubblesMaterial {
     BubblesMaterial {
         standard: StandardMaterial {
@@ -77,137 +207,42 @@ pub fn material_from_standard(standard: StandardMaterial) -> BubblesMaterial {
     }
 }
 
-#[derive(Resource, Debug, Default)]
-struct ExtractedMeshes {
-    extracted: HashMap<Handle<Mesh>, Mesh>,
-    removed: HashSet<Handle<Mesh>>,
+/// Counters for the work done drawing bubbles, shared between the main and render worlds.
+/// The render world adds to them as it goes, and [`BubblesStats::take`] resets them, so they
+/// hold the totals since the last time they were read.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct BubblesStats(Arc<BubblesCounters>);
+
+#[derive(Debug, Default)]
+pub struct BubblesCounters {
+    pub bubbles_drawn: AtomicU64,
+    pub queue_micros: AtomicU64,
 }
 
-// This isn't quite how normal extraction works, but I think it's okay to just keep
-// all the meshes around forever, updating instead of processing changes each frame
-fn extract_meshes(
-    mut events: Extract<EventReader<AssetEvent<Mesh>>>,
-    assets: Extract<Res<Assets<Mesh>>>,
-    mut extracted_meshes: ResMut<ExtractedMeshes>,
-) {
-    let mut changed_assets = HashSet::default();
-    let mut removed = Vec::new();
-    for event in events.iter() {
-        match event {
-            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
-                changed_assets.insert(handle.clone_weak());
-            }
-            AssetEvent::Removed { handle } => {
-                changed_assets.remove(handle);
-                removed.push(handle.clone_weak());
-            }
-        }
+impl BubblesStats {
+    pub fn counters(&self) -> &BubblesCounters {
+        &self.0
     }
 
-    let mut extracted = HashMap::new();
-    for handle in changed_assets.drain() {
-        if let Some(asset) = assets.get(&handle) {
-            extracted.insert(handle, asset.extract_asset());
-        }
+    /// Read a counter and reset it to zero.
+    pub fn take(counter: &AtomicU64) -> u64 {
+        counter.swap(0, Ordering::Relaxed)
     }
 
-    extracted_meshes.extracted.extend(extracted);
-    extracted_meshes.removed.extend(removed);
-}
-
-fn prepare_bubble_material(
-    mut prepared_materials: ResMut<RenderMaterials<BubblesMaterial>>,
-    meshes: Res<ExtractedMeshes>,
-    render_device: Res<RenderDevice>,
-    query: Query<(&Handle<Mesh>, &Handle<BubblesMaterial>)>,
-) {
-    for (mesh_handle, material_handle) in &query {
-        let Some(prepared_material) = prepared_materials.get_mut(material_handle)
-        else {
-            log::error!("no mat found for {material_handle:?}");
-            continue;
-        };
-
-        let Some(mesh) = meshes.extracted.get(mesh_handle)
-        else {
-            log::error!("no mesh found for {mesh_handle:?}: actual is {:?}", &meshes);
-            continue;
-        };
-        let vertex_buffer_data = mesh.get_vertex_buffer_data();
-
-        let vertex_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: Some("bubble vertex buf"),
-            contents: &vertex_buffer_data,
-            usage: BufferUsages::STORAGE | BufferUsages::VERTEX,
-        });
-
-        let mut layout_entries = BubblesMaterial::bind_group_layout_entries(&render_device);
-
-        static ONCE: Once = Once::new();
-        ONCE.call_once(|| {
-            for layout_entry in &mut layout_entries {
-                if layout_entry.binding == 101 {
-                    log::debug!("vertex storage layout is {layout_entry:#?}");
-                    log::debug!("vertex has size {}", mem::size_of::<Vertex>());
-                }
-            }
-
-            log::debug!("prepared material layout: {layout_entries:#?}");
-        });
-
-        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("bubbles bind group layout"),
-            entries: &layout_entries,
-        });
-
-        let entries = prepared_material
-            .bindings
-            .iter()
-            .map(|(index, binding)| {
-                let resource = if *index == 101 {
-                    vertex_buffer.as_entire_binding()
-                } else {
-                    binding.get_binding()
-                };
-
-                BindGroupEntry {
-                    binding: *index,
-                    resource,
-                }
-            })
-            .collect::<Vec<_>>();
-
-        prepared_material.bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-            label: Some("bubbles bind group"),
-            layout: &layout,
-            entries: &entries,
-        });
-
-        // HACK: smuggling this along as an "owned binding resource" is probably
-        // not really correct, and we should stick it in a dedicated mesh resource
-        // or something.
-        //
-        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: Some("bubbles render quad"),
-            contents: bytemuck::cast_slice(geom::QUAD_MESH),
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-        });
-
-        prepared_material
-            .bindings
-            .push((102, OwnedBindingResource::Buffer(buffer)));
+    fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
     }
 }
 
 mod geom {
     use bevy::prelude::*;
 
-    // z = 0, so that the quad renders in front of anything else. Ideally it would
-    // be nice to have this cover the whole viewport, but for now that's too expensive
-    const TOP_LEFT: Vec3 = Vec3::new(-0.4, 0.8, 0.0);
-    const TOP_RIGHT: Vec3 = Vec3::new(0.4, 0.8, 0.0);
-    const BOT_LEFT: Vec3 = Vec3::new(-0.4, -0.8, 0.0);
-    const BOT_RIGHT: Vec3 = Vec3::new(0.4, -0.8, 0.0);
+    // corners of the quad each bubble is drawn on. The vertex shader moves and scales it
+    // to cover the bubble in whichever view it's drawn in
+    const TOP_LEFT: Vec3 = Vec3::new(-1.0, 1.0, 0.0);
+    const TOP_RIGHT: Vec3 = Vec3::new(1.0, 1.0, 0.0);
+    const BOT_LEFT: Vec3 = Vec3::new(-1.0, -1.0, 0.0);
+    const BOT_RIGHT: Vec3 = Vec3::new(1.0, -1.0, 0.0);
 
     pub static QUAD_MESH: &[Vec3] = &[
         TOP_LEFT, BOT_LEFT, TOP_RIGHT, // upper-left half of the quad
@@ -215,27 +250,123 @@ mod geom {
     ];
 }
 
-#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[derive(AsBindGroup, TypeUuid, Reflect, Debug, Clone)]
 #[uuid = "68c25f8b-b16a-4630-aa6c-e0399e71fbd6"]
+#[bind_group_data(BubblesKey)]
 pub struct Bubbles {
     /// How big the bubbles should be
     #[uniform(100)]
     pub bubble_radius: f32,
 
-    /// A binding to reuse the vertex buffer as storage.
-    /// This binding will describe
+    /// A binding to reuse the vertex buffer as storage, filled in through [`MeshStorage`].
     #[storage(101, read_only)]
+    #[reflect(ignore)]
     pub mesh_vertex_buffer: Vec<Vertex>,
+
+    /// Tints the color the bubbles get from `color_source`
+    #[uniform(102)]
+    pub emissive_color: Color,
+
+    /// Multiplies the emissive color. Above 1.0 the bubbles are brighter than their source,
+    /// which only shows (and blooms) with an HDR camera
+    #[uniform(103)]
+    pub emissive_intensity: f32,
+
+    /// Where the bubbles get their color from. Each source is compiled separately, so
+    /// changing this switches pipelines
+    pub color_source: ColorSource,
+
+    /// The color for [`ColorSource::Tint`], and the start of [`ColorSource::Gradient`]
+    #[uniform(104)]
+    pub tint: Color,
+
+    /// The end of [`ColorSource::Gradient`]
+    #[uniform(105)]
+    pub gradient_color: Color,
+
+    /// The heights (in the mesh's local space) where [`ColorSource::Gradient`] starts and
+    /// ends. Bubbles outside of them get the color at the nearest end
+    #[uniform(106)]
+    pub gradient_heights: Vec2,
+
+    /// A binding for the mesh's vertex colors, filled in through [`MeshStorage`].
+    #[storage(107, read_only)]
+    #[reflect(ignore)]
+    pub mesh_colors: Vec<Vec4>,
+
+    /// How the bubbles are shaded. Like `color_source`, changing this switches pipelines
+    pub shading: BubbleShading,
+
+    /// Index of refraction for [`BubbleShading::Water`]. Higher bends the scene behind the
+    /// bubbles more, and makes them reflect more head-on
+    #[uniform(108)]
+    pub ior: f32,
+
+    /// How many times the thin-film colors cycle from the middle of a
+    /// [`BubbleShading::Water`] bubble out to its edge
+    #[uniform(109)]
+    pub thin_film_thickness: f32,
+
+    /// Tints the thin-film reflection of [`BubbleShading::Water`] bubbles
+    #[uniform(110)]
+    pub thin_film_tint: Color,
+}
+
+/// What colors the bubbles.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ColorSource {
+    /// The material's `base_color` times its `base_color_texture`
+    BaseColor,
+    /// The material's `emissive_texture`
+    #[default]
+    Emissive,
+    /// The mesh's `ATTRIBUTE_COLOR`, or white if it doesn't have any
+    VertexColor,
+    /// Just [`Bubbles::tint`]
+    Tint,
+    /// From [`Bubbles::tint`] to [`Bubbles::gradient_color`], by the height of each bubble
+    Gradient,
+}
+
+impl ColorSource {
+    fn shader_def(self) -> &'static str {
+        match self {
+            ColorSource::BaseColor => "BUBBLE_COLOR_BASE_COLOR",
+            ColorSource::Emissive => "BUBBLE_COLOR_EMISSIVE",
+            ColorSource::VertexColor => "BUBBLE_COLOR_VERTEX_COLOR",
+            ColorSource::Tint => "BUBBLE_COLOR_TINT",
+            ColorSource::Gradient => "BUBBLE_COLOR_GRADIENT",
+        }
+    }
+}
+
+/// How the bubbles are shaded.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BubbleShading {
+    /// Flat, in the color from [`Bubbles::color_source`] times the emissive color and
+    /// intensity
+    #[default]
+    Emissive,
+    /// Like water droplets: the scene behind each bubble is refracted through it, with a
+    /// fresnel reflection tinted by a thin film on top. The color source and emissive
+    /// settings aren't used
+    Water,
+}
+
+/// The parts of [`Bubbles`] which need a different pipeline.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BubblesKey {
+    color_source: ColorSource,
+    shading: BubbleShading,
 }
 
-/// A helper struct to represent the type of elements in the mesh vertex buffer,
-/// to make it easier to derive [`AsBindGroup`] for [`Bubbles`].
-#[derive(Debug, Clone, Copy, Pod, Zeroable, ShaderType)]
-#[repr(C)]
-pub struct Vertex {
-    position: Vec3,
-    normal: Vec3,
-    uv: Vec2,
+impl From<&Bubbles> for BubblesKey {
+    fn from(bubbles: &Bubbles) -> Self {
+        Self {
+            color_source: bubbles.color_source,
+            shading: bubbles.shading,
+        }
+    }
 }
 
 impl Default for Bubbles {
@@ -243,6 +374,17 @@ impl Default for Bubbles {
         Self {
             bubble_radius: 1.0,
             mesh_vertex_buffer: Vec::new(),
+            emissive_color: Color::WHITE,
+            emissive_intensity: 1.0,
+            color_source: ColorSource::Emissive,
+            tint: Color::WHITE,
+            gradient_color: Color::CYAN,
+            gradient_heights: Vec2::new(0.0, 1.5),
+            mesh_colors: Vec::new(),
+            shading: BubbleShading::Emissive,
+            ior: 1.33,
+            thin_film_thickness: 1.5,
+            thin_film_tint: Color::WHITE,
         }
     }
 }
@@ -260,27 +402,21 @@ impl Material for Bubbles {
         _pipeline: &MaterialPipeline<Self>,
         descriptor: &mut RenderPipelineDescriptor,
         _layout: &MeshVertexBufferLayout,
-        _key: MaterialPipelineKey<Self>,
+        key: MaterialPipelineKey<Self>,
     ) -> Result<(), SpecializedMeshPipelineError> {
         if let Some(label) = &mut descriptor.label {
             *label = format!("bubbles_{label}").into();
         }
 
-        // add another vertex buffer for the fullscreen quad so we render every fragment
-        descriptor.vertex.buffers = vec![VertexBufferLayout {
-            step_mode: VertexStepMode::Vertex,
-            array_stride: mem::size_of::<Vec3>() as u64,
-            attributes: vec![VertexAttribute {
-                format: VertexFormat::Float32x3,
-                offset: 0,
-                shader_location: 0,
-            }],
-        }];
-
-        log::debug!(
-            "updated vertex buffer layout: {:#?}",
-            descriptor.vertex.buffers,
-        );
+        // the vertex colors and gradient are picked per bubble, the textures per fragment
+        let color_source = key.bind_group_data.color_source.shader_def();
+        descriptor.vertex.shader_defs.push(color_source.into());
+        if let Some(fragment) = &mut descriptor.fragment {
+            fragment.shader_defs.push(color_source.into());
+            if key.bind_group_data.shading == BubbleShading::Water {
+                fragment.shader_defs.push("BUBBLE_SHADING_WATER".into());
+            }
+        }
 
         Ok(())
     }
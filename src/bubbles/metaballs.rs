@@ -0,0 +1,255 @@
+//! Metaball mode, where bubbles close to each other smoothly merge into blobs.
+//!
+//! Each fragment needs the bubbles around it, so every frame the bubble centers are projected
+//! on the CPU (the same way the shader does) and sorted into a grid of screen tiles, one grid
+//! per view and entity. Tiles are at least as big as a bubble plus its blend radius, so the
+//! bubbles affecting a fragment are always in its own tile or the 8 around it.
+//!
+//! Only the first bubble of each triangle is drawn, since the other two are in the exact same
+//! place and would just make the blobs fatter.
+
+use std::marker::PhantomData;
+
+use bevy::core::{Pod, Zeroable};
+use bevy::core_pipeline::core_3d::Transparent3d;
+use bevy::prelude::*;
+use bevy::render::render_phase::RenderPhase;
+use bevy::render::render_resource::{BufferInitDescriptor, BufferUsages};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::view::ExtractedView;
+use bevy::render::Extract;
+use bevy::utils::{HashMap, HashSet};
+
+use super::BubblesPass;
+use crate::mesh_storage::{PosedMeshes, ReusableBuffer, SizedBuffer};
+use crate::settings::EffectSettings;
+
+/// The most tiles along either side of the screen. Tiles are made bigger than needed rather
+/// than going over this, which keeps tiny bubbles from making huge grids.
+const MAX_TILES: u32 = 64;
+
+/// The biggest radius any of an entity's bubbles can have, from [`BubblesPass::bubble_radius`].
+#[derive(Component, Debug, Clone, Copy)]
+pub struct BubbleRadius(pub f32);
+
+pub fn extract_bubble_radii<M: BubblesPass>(
+    mut commands: Commands,
+    mut previous_len: Local<usize>,
+    query: Extract<Query<(Entity, &Handle<M>)>>,
+    materials: Extract<Res<Assets<M>>>,
+) {
+    let mut values = Vec::with_capacity(*previous_len);
+    for (entity, handle) in &query {
+        let Some(material) = materials.get(handle) else { continue };
+        values.push((entity, BubbleRadius(material.bubble_radius())));
+    }
+
+    *previous_len = values.len();
+    commands.insert_or_spawn_batch(values);
+}
+
+/// `BubbleBins` in `bubble_functions.wgsl`.
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct BinsUniform {
+    dims: UVec2,
+    tile_size: Vec2,
+    blend_radius: f32,
+    _padding: [f32; 3],
+}
+
+/// The buffers behind the bins bindings in `bubble_functions.wgsl`.
+#[derive(Debug, Clone)]
+pub struct MetaballBins {
+    pub uniform: SizedBuffer,
+    pub ranges: SizedBuffer,
+    pub entries: SizedBuffer,
+}
+
+impl MetaballBins {
+    /// A single empty tile, for when metaballs are off.
+    pub fn empty(render_device: &RenderDevice) -> Self {
+        let uniform = BinsUniform {
+            dims: UVec2::ONE,
+            tile_size: Vec2::splat(2.0),
+            blend_radius: 0.0,
+            _padding: [0.0; 3],
+        };
+        let buffer = |label, contents: &[u8], usage| {
+            SizedBuffer::from(
+                render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some(label),
+                    contents,
+                    usage,
+                }),
+            )
+        };
+
+        Self {
+            uniform: buffer(
+                "empty metaball bins uniform",
+                bytemuck::bytes_of(&uniform),
+                BufferUsages::UNIFORM,
+            ),
+            // storage buffers can't be empty
+            ranges: buffer(
+                "empty metaball bin ranges",
+                bytemuck::cast_slice(&[UVec2::ZERO]),
+                BufferUsages::STORAGE,
+            ),
+            entries: buffer(
+                "empty metaball bin entries",
+                bytemuck::cast_slice(&[0_u32]),
+                BufferUsages::STORAGE,
+            ),
+        }
+    }
+}
+
+/// Where one entity's [`MetaballBins`] in one view are written, kept between frames.
+struct MetaballBinBuffers {
+    uniform: ReusableBuffer,
+    ranges: ReusableBuffer,
+    entries: ReusableBuffer,
+}
+
+impl MetaballBinBuffers {
+    fn new() -> Self {
+        Self {
+            uniform: ReusableBuffer::new("metaball bins uniform", BufferUsages::UNIFORM),
+            ranges: ReusableBuffer::new("metaball bin ranges", BufferUsages::STORAGE),
+            entries: ReusableBuffer::new("metaball bin entries", BufferUsages::STORAGE),
+        }
+    }
+
+    fn write(
+        &mut self,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        uniform: &BinsUniform,
+        ranges: &[UVec2],
+        entries: &[u32],
+    ) -> MetaballBins {
+        MetaballBins {
+            uniform: self
+                .uniform
+                .write(render_device, render_queue, bytemuck::bytes_of(uniform)),
+            ranges: self
+                .ranges
+                .write(render_device, render_queue, bytemuck::cast_slice(ranges)),
+            entries: self
+                .entries
+                .write(render_device, render_queue, bytemuck::cast_slice(entries)),
+        }
+    }
+}
+
+/// The bins for every entity drawn as `M` in this view.
+#[derive(Component)]
+pub struct ViewMetaballBins<M> {
+    pub bins: HashMap<Entity, MetaballBins>,
+    marker: PhantomData<M>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn prepare_metaball_bins<M: BubblesPass>(
+    mut commands: Commands,
+    settings: Res<EffectSettings>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    posed: Res<PosedMeshes>,
+    // by view and entity
+    mut buffers: Local<HashMap<(Entity, Entity), MetaballBinBuffers>>,
+    views: Query<(Entity, &ExtractedView), With<RenderPhase<Transparent3d>>>,
+    bubbles: Query<(Entity, &BubbleRadius), With<Handle<M>>>,
+) {
+    if !settings.metaballs {
+        buffers.clear();
+        return;
+    }
+
+    let mut written = HashSet::default();
+    for (view_entity, view) in &views {
+        let view_proj = view.view_projection.unwrap_or_else(|| {
+            view.projection * view.transform.compute_matrix().inverse()
+        });
+        let aspect = view.viewport.z as f32 / view.viewport.w.max(1) as f32;
+
+        let mut view_bins = HashMap::default();
+        for (entity, radius) in &bubbles {
+            // invalid meshes were already reported
+            let Some(posed) = posed.get(entity) else { continue };
+
+            // in NDC, where radii are measured in half viewport heights
+            let reach = (radius.0 + settings.metaball_blend_radius).max(f32::EPSILON);
+            let dims = (Vec2::new(2.0 * aspect, 2.0) / reach)
+                .floor()
+                .as_uvec2()
+                .clamp(UVec2::ONE, UVec2::splat(MAX_TILES));
+            let tile_size = Vec2::splat(2.0) / dims.as_vec2();
+
+            // the first instance of each whole triangle the draw covers. The vertices are
+            // de-indexed, so these are the mesh's faces
+            let instance_count = posed.instance_count(settings.max_bubbles) as usize;
+            let mut binned: Vec<(u32, u32)> = posed
+                .centroids
+                .iter()
+                .enumerate()
+                .map(|(triangle, centroid)| (triangle * 3, centroid))
+                .filter(|(first, _)| first + 3 <= posed.vertices.len() && *first < instance_count)
+                .filter_map(|(first, centroid)| {
+                    let clip = view_proj * centroid.extend(1.0);
+                    // behind the camera, see bubble_quad_clip_position
+                    if clip.w <= 0.0 {
+                        return None;
+                    }
+
+                    let ndc = clip.truncate().truncate() / clip.w;
+                    let tile = ((ndc + 1.0) / tile_size)
+                        .floor()
+                        .clamp(Vec2::ZERO, (dims - 1).as_vec2())
+                        .as_uvec2();
+                    Some((tile.y * dims.x + tile.x, first as u32))
+                })
+                .collect();
+            binned.sort_unstable();
+
+            // (start, count) into the entries, for every tile
+            let mut ranges = vec![UVec2::ZERO; (dims.x * dims.y) as usize];
+            for (i, &(tile, _)) in binned.iter().enumerate() {
+                let range = &mut ranges[tile as usize];
+                if range.y == 0 {
+                    range.x = i as u32;
+                }
+                range.y += 1;
+            }
+            let mut entries: Vec<u32> = binned.iter().map(|&(_, instance)| instance).collect();
+            // storage buffers can't be empty
+            if entries.is_empty() {
+                entries.push(0);
+            }
+
+            let uniform = BinsUniform {
+                dims,
+                tile_size,
+                blend_radius: settings.metaball_blend_radius,
+                _padding: [0.0; 3],
+            };
+
+            let bins = buffers
+                .entry((view_entity, entity))
+                .or_insert_with(MetaballBinBuffers::new)
+                .write(&render_device, &render_queue, &uniform, &ranges, &entries);
+            view_bins.insert(entity, bins);
+            written.insert((view_entity, entity));
+        }
+
+        commands.entity(view_entity).insert(ViewMetaballBins::<M> {
+            bins: view_bins,
+            marker: PhantomData,
+        });
+    }
+
+    // views and entities that have gone
+    buffers.retain(|key, _| written.contains(key));
+}
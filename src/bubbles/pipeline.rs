@@ -1,51 +1,161 @@
-//! Sketch of what a custom pipeline might look like, to plumb the mesh vertex buffer
-//! into the material shader as a buffer uniform.
+//! Draws bubbles in the transparent pass. Each entity is drawn with its material's own
+//! pipeline, specialized to use the material's bubble entry points and draw instanced
+//! [`BubbleQuad`]s instead of the mesh, one instance per bubble.
+//!
+//! The material's bind group is swapped for the entity's [`MeshStorageBindGroup`], a copy of
+//! it whose storage bindings hold the entity's posed mesh, so the vertex shader can find each
+//! bubble's triangle by its instance index.
+
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::mem;
+use std::time::Instant;
 
 use bevy::core_pipeline::core_3d::Transparent3d;
 use bevy::ecs::query::ROQueryItem;
 use bevy::ecs::system::lifetimeless::*;
 use bevy::ecs::system::SystemParamItem;
+use bevy::log;
 use bevy::pbr::{
     MaterialPipeline, MaterialPipelineKey, MeshPipelineKey, MeshUniform, RenderMaterials,
-    SetMaterialBindGroup, SetMeshBindGroup, SetMeshViewBindGroup,
+    SetMeshBindGroup, SetMeshViewBindGroup,
 };
 use bevy::prelude::*;
-use bevy::render::mesh::GpuBufferInfo;
+use bevy::render::mesh::MeshVertexBufferLayout;
 use bevy::render::render_asset::*;
 use bevy::render::render_phase::*;
 use bevy::render::render_resource::{
-    OwnedBindingResource, PipelineCache, SpecializedMeshPipelines,
+    BindGroupLayout, Buffer, BufferInitDescriptor, BufferUsages, PipelineCache,
+    RenderPipelineDescriptor, SpecializedMeshPipeline, SpecializedMeshPipelineError,
+    SpecializedMeshPipelines, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode,
 };
+use bevy::render::renderer::RenderDevice;
 use bevy::render::view::ExtractedView;
-use inline_tweak::tweak;
 
-use super::BubblesMaterial;
+use super::view::{BubblesViewLayout, SetBubblesViewBindGroup};
+use super::{geom, BubblesPass, BubblesStats};
+use crate::mesh_storage::{MeshStorageBindGroup, PosedMeshes};
+use crate::settings::EffectSettings;
 
-pub type DrawCustom = (
+pub type DrawCustom<M> = (
     SetItemPipeline,
     SetMeshViewBindGroup<0>,
-    SetMaterialBindGroup<BubblesMaterial, 1>, // skipped because we set the bind group in `Draw`
+    // no SetMaterialBindGroup, DrawBubblesMaterial binds the entity's own copy at 1
     SetMeshBindGroup<2>,
-    DrawBubblesMaterial,
+    SetBubblesViewBindGroup<M, 3>,
+    DrawBubblesMaterial<M>,
 );
 
-pub fn queue_draw_bubbles(
+/// The quad every bubble is drawn on, shared by all bubbles materials.
+#[derive(Resource)]
+pub struct BubbleQuad {
+    vertex_buffer: Buffer,
+}
+
+impl BubbleQuad {
+    pub fn vertex_buffer_layout() -> VertexBufferLayout {
+        VertexBufferLayout {
+            step_mode: VertexStepMode::Vertex,
+            array_stride: mem::size_of::<Vec3>() as u64,
+            attributes: vec![VertexAttribute {
+                format: VertexFormat::Float32x3,
+                offset: 0,
+                shader_location: 0,
+            }],
+        }
+    }
+}
+
+impl FromWorld for BubbleQuad {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        Self {
+            vertex_buffer: render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("bubbles render quad"),
+                contents: bytemuck::cast_slice(geom::QUAD_MESH),
+                usage: BufferUsages::VERTEX,
+            }),
+        }
+    }
+}
+
+/// Wraps the regular [`MaterialPipeline`] for `M`, but draws instanced quads instead of the
+/// mesh itself. This needs to be a separate pipeline so that materials which are also drawn
+/// normally don't collide with the bubbles in the specialized pipeline cache.
+#[derive(Resource)]
+pub struct BubblesPipeline<M: BubblesPass> {
+    material_pipeline: MaterialPipeline<M>,
+    view_layout: BindGroupLayout,
+}
+
+impl<M: BubblesPass> FromWorld for BubblesPipeline<M> {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            material_pipeline: MaterialPipeline::from_world(world),
+            view_layout: world.resource::<BubblesViewLayout>().layout.clone(),
+        }
+    }
+}
+
+impl<M: BubblesPass> SpecializedMeshPipeline for BubblesPipeline<M>
+where
+    M::Data: PartialEq + Eq + Hash + Clone,
+{
+    /// The material's key, and whether metaballs are on
+    type Key = (MaterialPipelineKey<M>, bool);
+
+    fn specialize(
+        &self,
+        (key, metaballs): Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.material_pipeline.specialize(key, layout)?;
+
+        descriptor.vertex.entry_point = M::VERTEX_ENTRY_POINT.into();
+        if let Some(fragment) = &mut descriptor.fragment {
+            fragment.entry_point = M::FRAGMENT_ENTRY_POINT.into();
+        }
+
+        descriptor.layout.push(self.view_layout.clone());
+        if metaballs {
+            descriptor.vertex.shader_defs.push("BUBBLE_METABALLS".into());
+            if let Some(fragment) = &mut descriptor.fragment {
+                fragment.shader_defs.push("BUBBLE_METABALLS".into());
+            }
+        }
+
+        // replace the mesh's vertex buffer with the quad each bubble is drawn on
+        descriptor.vertex.buffers = vec![BubbleQuad::vertex_buffer_layout()];
+
+        log::debug!(
+            "updated vertex buffer layout: {:#?}",
+            descriptor.vertex.buffers,
+        );
+
+        Ok(descriptor)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn queue_draw_bubbles<M: BubblesPass>(
     transparent_3d_draw_functions: Res<DrawFunctions<Transparent3d>>,
-    material_pipeline: Res<MaterialPipeline<BubblesMaterial>>,
+    bubbles_pipeline: Res<BubblesPipeline<M>>,
     msaa: Res<Msaa>,
-    mut pipelines: ResMut<SpecializedMeshPipelines<MaterialPipeline<BubblesMaterial>>>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<BubblesPipeline<M>>>,
     pipeline_cache: Res<PipelineCache>,
     render_meshes: Res<RenderAssets<Mesh>>,
-    render_materials: Res<RenderMaterials<BubblesMaterial>>,
-    material_meshes: Query<(
-        Entity,
-        &Handle<BubblesMaterial>,
-        &MeshUniform,
-        &Handle<Mesh>,
-    )>,
+    render_materials: Res<RenderMaterials<M>>,
+    material_meshes: Query<(Entity, &Handle<M>, &MeshUniform, &Handle<Mesh>)>,
     mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>)>,
-) {
-    let draw_custom = transparent_3d_draw_functions.read().id::<DrawCustom>();
+    posed_meshes: Res<PosedMeshes>,
+    settings: Res<EffectSettings>,
+    stats: Res<BubblesStats>,
+) where
+    M::Data: PartialEq + Eq + Hash + Clone,
+{
+    let start = Instant::now();
+    let draw_custom = transparent_3d_draw_functions.read().id::<DrawCustom<M>>();
 
     let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
 
@@ -65,7 +175,12 @@ pub fn queue_draw_bubbles(
                 };
 
                 let pipeline = pipelines
-                    .specialize(&pipeline_cache, &material_pipeline, key, &mesh.layout)
+                    .specialize(
+                        &pipeline_cache,
+                        &bubbles_pipeline,
+                        (key, settings.metaballs),
+                        &mesh.layout,
+                    )
                     .unwrap();
 
                 transparent_phase.add(Transparent3d {
@@ -74,53 +189,63 @@ pub fn queue_draw_bubbles(
                     draw_function: draw_custom,
                     distance: rangefinder.distance(&mesh_uniform.transform),
                 });
+
+                // counted here rather than when drawing, which happens for the shadows and
+                // prepasses too
+                if let Some(posed) = posed_meshes.get(entity) {
+                    let instance_count = posed.instance_count(settings.max_bubbles);
+                    BubblesStats::add(&stats.counters().bubbles_drawn, instance_count as u64);
+                }
             }
         }
     }
+
+    BubblesStats::add(&stats.counters().queue_micros, start.elapsed().as_micros() as u64);
 }
 
-pub struct DrawBubblesMaterial;
+/// Binds the entity's [`MeshStorageBindGroup`] at 1, in place of the material's bind group,
+/// then draws a quad for each bubble.
+pub struct DrawBubblesMaterial<M>(PhantomData<M>);
 
-impl<P: PhaseItem> RenderCommand<P> for DrawBubblesMaterial {
+impl<P: PhaseItem, M: BubblesPass> RenderCommand<P> for DrawBubblesMaterial<M> {
     type Param = (
-        SRes<RenderAssets<Mesh>>,
-        SRes<RenderMaterials<BubblesMaterial>>,
+        SRes<PosedMeshes>,
+        SRes<RenderMaterials<M>>,
+        SRes<EffectSettings>,
+        SRes<BubbleQuad>,
     );
 
     type ViewWorldQuery = ();
 
-    type ItemWorldQuery = (Read<Handle<Mesh>>, Read<Handle<BubblesMaterial>>);
+    type ItemWorldQuery = (
+        Entity,
+        Read<Handle<M>>,
+        Option<Read<MeshStorageBindGroup<M>>>,
+    );
 
     fn render<'w>(
         _item: &P,
         _view: (),
-        (mesh_handle, material_handle): ROQueryItem<'_, Self::ItemWorldQuery>,
-        (meshes, prepared_materials): SystemParamItem<'w, '_, Self::Param>,
+        (entity, material_handle, storage): ROQueryItem<'w, Self::ItemWorldQuery>,
+        (posed, prepared_materials, settings, quad): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        let (Some(prepared_material), Some(mesh)) = (
+        // entities whose mesh couldn't be prepared don't have storage
+        let (Some(_), Some(posed), Some(storage)) = (
             prepared_materials.into_inner().get(material_handle),
-            meshes.into_inner().get(mesh_handle),
+            posed.into_inner().get(entity),
+            storage,
         ) else { return RenderCommandResult::Failure };
 
-        let Some((_ , OwnedBindingResource::Buffer(quad_vertex_buffer))) = prepared_material
-            .bindings
-            .iter()
-            .find(|(binding, _)| *binding == 102)
-        else { return RenderCommandResult::Failure };
-
-        pass.set_vertex_buffer(0, quad_vertex_buffer.slice(..));
-
-        let instance_count = match &mesh.buffer_info {
-            GpuBufferInfo::Indexed { count, .. } => *count,
-            GpuBufferInfo::NonIndexed { vertex_count } => *vertex_count,
-        };
+        pass.set_bind_group(1, &storage.bind_group, &[]);
+        pass.set_vertex_buffer(0, quad.into_inner().vertex_buffer.slice(..));
 
         // we know the quad buffer is non-indexed with fixed number of verts,
-        // draw it directly. clamp the instance count for performance, but
+        // draw it directly. the instance count is clamped for performance, but
         // ideally we really ought to be skipping a bunch of tris to trim this
         // down *before* sending to the GPU. Maybe extraction could do that
-        pass.draw(0..6, 0..instance_count.min(tweak!(200)));
+        let instance_count = posed.instance_count(settings.max_bubbles);
+        pass.draw(0..geom::QUAD_MESH.len() as u32, 0..instance_count);
 
         RenderCommandResult::Success
     }
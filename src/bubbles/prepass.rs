@@ -0,0 +1,278 @@
+//! Draws bubbles into shadow maps and the depth/normal prepasses, with the material's
+//! [`prepass_shader`](BubblesPass::prepass_shader). The bubbles are cut out of their quads
+//! in the fragment shader, so in the prepasses they go in with the alpha masked meshes.
+//!
+//! Bubbles are sized for the camera, so in a shadow map they're projected to be as big as
+//! they are in world space (see [`view`](super::view)). With metaballs, shadows are cast by the
+//! separate bubbles, since the bins are only made for cameras.
+
+use std::hash::Hash;
+
+use bevy::core_pipeline::prepass::{AlphaMask3dPrepass, DepthPrepass, NormalPrepass};
+use bevy::pbr::{
+    CascadesVisibleEntities, CubemapVisibleEntities, ExtractedDirectionalLight,
+    ExtractedPointLight, LightEntity, MaterialPipelineKey, MeshPipelineKey, MeshUniform,
+    NotShadowCaster, PrepassPipeline, RenderMaterials, SetMeshBindGroup,
+    SetPrepassViewBindGroup, Shadow, ViewLightEntities,
+};
+use bevy::prelude::*;
+use bevy::render::mesh::MeshVertexBufferLayout;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_phase::{DrawFunctions, RenderPhase, SetItemPipeline};
+use bevy::render::render_resource::{
+    BindGroupLayout, FragmentState, PipelineCache, RenderPipelineDescriptor, ShaderRef,
+    SpecializedMeshPipeline, SpecializedMeshPipelineError, SpecializedMeshPipelines,
+};
+use bevy::render::view::{ExtractedView, VisibleEntities};
+
+use super::pipeline::{BubbleQuad, DrawBubblesMaterial};
+use super::view::{BubblesViewLayout, SetBubblesViewBindGroup};
+use super::BubblesPass;
+use crate::settings::EffectSettings;
+
+pub type DrawBubblesPrepass<M> = (
+    SetItemPipeline,
+    SetPrepassViewBindGroup<0>,
+    // the material's bind group is set by DrawBubblesMaterial
+    SetMeshBindGroup<2>,
+    SetBubblesViewBindGroup<M, 3>,
+    DrawBubblesMaterial<M>,
+);
+
+/// Wraps the [`PrepassPipeline`] for `M` the same way
+/// [`BubblesPipeline`](super::pipeline::BubblesPipeline) wraps its material pipeline.
+#[derive(Resource)]
+pub struct BubblesPrepassPipeline<M: BubblesPass> {
+    prepass_pipeline: PrepassPipeline<M>,
+    view_layout: BindGroupLayout,
+    /// `None` if the material doesn't have a prepass shader, in which case its bubbles are
+    /// left out of the prepasses and don't cast shadows
+    shader: Option<Handle<Shader>>,
+}
+
+impl<M: BubblesPass> FromWorld for BubblesPrepassPipeline<M> {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource::<AssetServer>();
+        let shader = match M::prepass_shader() {
+            ShaderRef::Default => None,
+            ShaderRef::Handle(handle) => Some(handle),
+            ShaderRef::Path(path) => Some(asset_server.load(path)),
+        };
+
+        Self {
+            prepass_pipeline: PrepassPipeline::from_world(world),
+            view_layout: world.resource::<BubblesViewLayout>().layout.clone(),
+            shader,
+        }
+    }
+}
+
+impl<M: BubblesPass> SpecializedMeshPipeline for BubblesPrepassPipeline<M>
+where
+    M::Data: PartialEq + Eq + Hash + Clone,
+{
+    /// The material's key, and whether metaballs are on
+    type Key = (MaterialPipelineKey<M>, bool);
+
+    fn specialize(
+        &self,
+        (key, metaballs): Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.prepass_pipeline.specialize(key, layout)?;
+        // the queue systems skip materials without one
+        let shader = self.shader.clone().expect("bubbles prepass needs a shader");
+
+        descriptor.label = Some("bubbles_prepass_pipeline".into());
+        descriptor.vertex.shader = shader.clone();
+        descriptor.vertex.entry_point = "vertex".into();
+
+        // the prepass only has a fragment shader when it writes normals, but bubbles always
+        // need one to cut them out of their quads
+        descriptor.fragment = Some(match descriptor.fragment.take() {
+            Some(fragment) => FragmentState {
+                shader,
+                entry_point: "fragment".into(),
+                ..fragment
+            },
+            None => FragmentState {
+                shader,
+                entry_point: "fragment".into(),
+                shader_defs: descriptor.vertex.shader_defs.clone(),
+                targets: Vec::new(),
+            },
+        });
+
+        descriptor.layout.push(self.view_layout.clone());
+        if metaballs {
+            descriptor.vertex.shader_defs.push("BUBBLE_METABALLS".into());
+            if let Some(fragment) = &mut descriptor.fragment {
+                fragment.shader_defs.push("BUBBLE_METABALLS".into());
+            }
+        }
+
+        descriptor.vertex.buffers = vec![BubbleQuad::vertex_buffer_layout()];
+
+        Ok(descriptor)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn queue_bubbles_prepass<M: BubblesPass>(
+    draw_functions: Res<DrawFunctions<AlphaMask3dPrepass>>,
+    prepass_pipeline: Res<BubblesPrepassPipeline<M>>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<BubblesPrepassPipeline<M>>>,
+    pipeline_cache: Res<PipelineCache>,
+    msaa: Res<Msaa>,
+    render_meshes: Res<RenderAssets<Mesh>>,
+    render_materials: Res<RenderMaterials<M>>,
+    settings: Res<EffectSettings>,
+    material_meshes: Query<(&Handle<M>, &Handle<Mesh>, &MeshUniform)>,
+    mut views: Query<(
+        &ExtractedView,
+        &VisibleEntities,
+        &mut RenderPhase<AlphaMask3dPrepass>,
+        Option<&DepthPrepass>,
+        Option<&NormalPrepass>,
+    )>,
+) where
+    M::Data: PartialEq + Eq + Hash + Clone,
+{
+    if prepass_pipeline.shader.is_none() {
+        return;
+    }
+
+    let draw_bubbles = draw_functions.read().id::<DrawBubblesPrepass<M>>();
+
+    for (view, visible_entities, mut phase, depth_prepass, normal_prepass) in &mut views {
+        let mut view_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
+        if depth_prepass.is_some() {
+            view_key |= MeshPipelineKey::DEPTH_PREPASS;
+        }
+        if normal_prepass.is_some() {
+            view_key |= MeshPipelineKey::NORMAL_PREPASS;
+        }
+
+        let rangefinder = view.rangefinder3d();
+
+        for &entity in &visible_entities.entities {
+            let Ok((material_handle, mesh_handle, mesh_uniform)) = material_meshes.get(entity)
+            else {
+                continue;
+            };
+            let (Some(material), Some(mesh)) = (
+                render_materials.get(material_handle),
+                render_meshes.get(mesh_handle),
+            ) else { continue };
+
+            let key = MaterialPipelineKey {
+                mesh_key: view_key
+                    | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology),
+                bind_group_data: material.key.clone(),
+            };
+            let pipeline_id = pipelines
+                .specialize(
+                    &pipeline_cache,
+                    &prepass_pipeline,
+                    (key, settings.metaballs),
+                    &mesh.layout,
+                )
+                .unwrap();
+
+            phase.add(AlphaMask3dPrepass {
+                entity,
+                draw_function: draw_bubbles,
+                pipeline_id,
+                distance: rangefinder.distance(&mesh_uniform.transform),
+            });
+        }
+    }
+}
+
+/// Mostly copied from `queue_shadows`.
+#[allow(clippy::too_many_arguments)]
+pub fn queue_bubble_shadows<M: BubblesPass>(
+    draw_functions: Res<DrawFunctions<Shadow>>,
+    prepass_pipeline: Res<BubblesPrepassPipeline<M>>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<BubblesPrepassPipeline<M>>>,
+    pipeline_cache: Res<PipelineCache>,
+    render_meshes: Res<RenderAssets<Mesh>>,
+    render_materials: Res<RenderMaterials<M>>,
+    casting_meshes: Query<(&Handle<Mesh>, &Handle<M>), Without<NotShadowCaster>>,
+    view_lights: Query<(Entity, &ViewLightEntities)>,
+    mut view_light_shadow_phases: Query<(&LightEntity, &mut RenderPhase<Shadow>)>,
+    point_light_entities: Query<&CubemapVisibleEntities, With<ExtractedPointLight>>,
+    directional_light_entities: Query<&CascadesVisibleEntities, With<ExtractedDirectionalLight>>,
+    spot_light_entities: Query<&VisibleEntities, With<ExtractedPointLight>>,
+) where
+    M::Data: PartialEq + Eq + Hash + Clone,
+{
+    if prepass_pipeline.shader.is_none() {
+        return;
+    }
+
+    let draw_bubbles = draw_functions.read().id::<DrawBubblesPrepass<M>>();
+
+    for (view_entity, view_lights) in &view_lights {
+        for &view_light_entity in &view_lights.lights {
+            let Ok((light_entity, mut shadow_phase)) =
+                view_light_shadow_phases.get_mut(view_light_entity)
+            else {
+                continue;
+            };
+
+            let visible_entities = match light_entity {
+                LightEntity::Directional {
+                    light_entity,
+                    cascade_index,
+                } => directional_light_entities
+                    .get(*light_entity)
+                    .ok()
+                    .and_then(|cascades| cascades.entities.get(&view_entity))
+                    .and_then(|cascades| cascades.get(*cascade_index)),
+                LightEntity::Point {
+                    light_entity,
+                    face_index,
+                } => point_light_entities
+                    .get(*light_entity)
+                    .ok()
+                    .map(|cubemap| cubemap.get(*face_index)),
+                LightEntity::Spot { light_entity } => spot_light_entities.get(*light_entity).ok(),
+            };
+            let Some(visible_entities) = visible_entities else { continue };
+
+            let mut light_key = MeshPipelineKey::DEPTH_PREPASS;
+            if matches!(light_entity, LightEntity::Directional { .. }) {
+                light_key |= MeshPipelineKey::DEPTH_CLAMP_ORTHO;
+            }
+
+            // NOTE: Lights with shadow mapping disabled will have no visible entities
+            for &entity in visible_entities.iter() {
+                let Ok((mesh_handle, material_handle)) = casting_meshes.get(entity) else {
+                    continue;
+                };
+                let (Some(mesh), Some(material)) = (
+                    render_meshes.get(mesh_handle),
+                    render_materials.get(material_handle),
+                ) else { continue };
+
+                let key = MaterialPipelineKey {
+                    mesh_key: light_key
+                        | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology),
+                    bind_group_data: material.key.clone(),
+                };
+                // no metaballs, see the module docs
+                let pipeline = pipelines
+                    .specialize(&pipeline_cache, &prepass_pipeline, (key, false), &mesh.layout)
+                    .unwrap();
+
+                shadow_phase.add(Shadow {
+                    draw_function: draw_bubbles,
+                    pipeline,
+                    entity,
+                    distance: 0.0,
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! A software version of the bubbles pass, for checking the math in `bubbles.wgsl` and the
+//! bubble half of `teleport.wgsl` without a GPU. Each step mirrors the shaders (including
+//! their quirks), so if the two disagree it's the shader that changed. The tests at the
+//! bottom compare it against the images in `tests/golden`.
+//!
+//! Not mirrored: MSAA, the exact rounding of the GPU's texture filtering, metaballs, any
+//! [`ColorSource`](super::ColorSource) other than `Emissive`, and
+//! [`BubbleShading::Water`](super::BubbleShading::Water).
+
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+use self::noise::dissolve_threshold;
+use super::Bubbles;
+use crate::mesh_storage::{self, Vertex};
+use crate::teleport::Teleport;
+
+mod noise;
+
+/// Where the bubbles are seen from.
+#[derive(Debug, Clone, Copy)]
+pub struct ReferenceView {
+    /// The same as `view.view_proj` in the shader
+    pub view_proj: Mat4,
+    /// Size of the render target in pixels
+    pub size: UVec2,
+}
+
+/// Which shader's bubbles to draw.
+#[derive(Debug, Clone, Copy)]
+pub enum ReferenceMaterial<'a> {
+    /// `bubbles.wgsl`
+    Bubbles(&'a Bubbles),
+    /// `bubble_vertex` and `bubble_fragment` in `teleport.wgsl`, where bubbles grow as the
+    /// mesh dissolves around them
+    Teleport(&'a Teleport),
+}
+
+/// `BUBBLE_GROWTH_WIDTH` in `teleport_functions.wgsl`
+const BUBBLE_GROWTH_WIDTH: f32 = 0.1;
+
+impl ReferenceMaterial<'_> {
+    fn bubble_radius(&self) -> f32 {
+        match self {
+            ReferenceMaterial::Bubbles(bubbles) => bubbles.bubble_radius,
+            ReferenceMaterial::Teleport(teleport) => teleport.bubble_radius,
+        }
+    }
+
+    fn emissive(&self) -> (Color, f32) {
+        match self {
+            ReferenceMaterial::Bubbles(bubbles) => {
+                (bubbles.emissive_color, bubbles.emissive_intensity)
+            }
+            ReferenceMaterial::Teleport(teleport) => {
+                (teleport.emissive_color, teleport.emissive_intensity)
+            }
+        }
+    }
+
+    /// `bubble_growth` in `teleport_functions.wgsl`, for the triangle with these local
+    /// positions. Bubbles from `bubbles.wgsl` are always fully grown.
+    fn growth(&self, triangle: [Vec3; 3]) -> f32 {
+        let ReferenceMaterial::Teleport(teleport) = self else { return 1.0 };
+
+        let centroid_local_position = (triangle[0] + triangle[1] + triangle[2]) / 3.0;
+        let threshold = dissolve_threshold(centroid_local_position, teleport.noise_scale);
+        smoothstep(threshold, threshold + BUBBLE_GROWTH_WIDTH, teleport.progress)
+    }
+}
+
+/// WGSL's `smoothstep`
+fn smoothstep(low: f32, high: f32, x: f32) -> f32 {
+    let t = ((x - low) / (high - low)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// What the shader's vertex entry point passes along for one instance.
+struct BubbleInstance {
+    uv: Vec2,
+    centroid_clip_position: Vec4,
+    growth: f32,
+}
+
+/// `bubble_triangle` and the rest of the vertex entry point.
+fn bubble_instance(
+    vertices: &[Vertex],
+    model: Mat4,
+    view_proj: Mat4,
+    material: ReferenceMaterial,
+    instance_index: usize,
+) -> BubbleInstance {
+    // out of bounds storage reads are clamped (by wgpu's robustness checks), so do the same
+    let vertex = |index: usize| vertices[index.min(vertices.len() - 1)];
+
+    let first = instance_index - instance_index % 3;
+    let triangle = [vertex(first), vertex(first + 1), vertex(first + 2)];
+    let current_vert = triangle[instance_index % 3];
+
+    // bubble_centroid_world_position
+    let centroid_world_position = triangle
+        .iter()
+        .map(|vert| model * vert.position.extend(1.0))
+        .fold(Vec4::ZERO, |sum, position| sum + position)
+        / 3.0;
+
+    BubbleInstance {
+        uv: current_vert.uv,
+        centroid_clip_position: view_proj * centroid_world_position,
+        growth: material.growth(triangle.map(|vert| vert.position)),
+    }
+}
+
+/// `bubble_ray_distance`, for the fragment at `frag_coord` (in pixels, from the top left).
+fn bubble_ray_distance(frag_coord: Vec2, viewport: Vec2, sphere_center: Vec4) -> f32 {
+    let viewport_uv = frag_coord / viewport;
+    let frag_ndc = Vec2::new(viewport_uv.x * 2.0 - 1.0, 1.0 - viewport_uv.y * 2.0);
+
+    let mut offset = frag_ndc - sphere_center.truncate().truncate() / sphere_center.w;
+    offset.x *= viewport.x / viewport.y;
+
+    offset.length()
+}
+
+/// Bilinearly sample `texture` at `uv` with repeat wrapping, returning linear RGBA. No
+/// texture samples as white, like bevy's fallback image.
+fn sample(texture: Option<&Image>, uv: Vec2) -> Vec4 {
+    let Some(texture) = texture else { return Vec4::ONE };
+
+    let size = texture.size().as_uvec2();
+    let texel = |x: i32, y: i32| {
+        let x = x.rem_euclid(size.x as i32) as usize;
+        let y = y.rem_euclid(size.y as i32) as usize;
+        let i = (y * size.x as usize + x) * 4;
+        let [r, g, b, a] = [0, 1, 2, 3].map(|c| texture.data[i + c] as f32 / 255.0);
+        Vec4::from(Color::rgba(r, g, b, a).as_linear_rgba_f32())
+    };
+
+    let position = uv * size.as_vec2() - 0.5;
+    let (x, y) = (position.x.floor() as i32, position.y.floor() as i32);
+    let t = position.fract();
+
+    let top = texel(x, y).lerp(texel(x + 1, y), t.x);
+    let bottom = texel(x, y + 1).lerp(texel(x + 1, y + 1), t.x);
+    top.lerp(bottom, t.y)
+}
+
+/// Draw `mesh` (transformed by `model`) as bubbles, the same way the GPU would with
+/// `material` and the emissive texture `emissive`. At most `max_bubbles` are drawn, back to
+/// front (as with `sort_bubbles` on), alpha blended over `clear_color`.
+///
+/// Returns `None` if the mesh is empty or missing attributes.
+pub fn render_bubbles(
+    mesh: &Mesh,
+    model: Mat4,
+    view: ReferenceView,
+    material: ReferenceMaterial,
+    emissive: Option<&Image>,
+    max_bubbles: u32,
+    clear_color: Color,
+) -> Option<Image> {
+    // the same vertex buffer the shader gets as storage
+    let vertices = mesh_storage::deindex(mesh, mesh_storage::mesh_vertices(mesh)?);
+    if vertices.is_empty() || view.size.cmpeq(UVec2::ZERO).any() {
+        return None;
+    }
+
+    // the sampling above assumes 8 bit RGBA texels
+    let emissive = match emissive {
+        Some(image) if image.texture_descriptor.format == TextureFormat::Rgba8UnormSrgb => {
+            Some(image.clone())
+        }
+        Some(image) => Some(image.convert(TextureFormat::Rgba8UnormSrgb)?),
+        None => None,
+    };
+
+    // bubble_quad_clip_position collapses the quads of bubbles behind the camera. The rest
+    // of each quad is exactly the square around the bubble, so checking the distance alone
+    // covers it
+    let mut instances: Vec<_> = (0..vertices.len().min(max_bubbles as usize))
+        .map(|i| bubble_instance(&vertices, model, view.view_proj, material, i))
+        .filter(|instance| instance.centroid_clip_position.w > 0.0)
+        .collect();
+
+    // sorting.rs sorts by view space depth, which is in the same order as the depth in NDC.
+    // That's reversed, so the furthest bubbles have the lowest depth
+    let ndc_depth = |instance: &BubbleInstance| {
+        instance.centroid_clip_position.z / instance.centroid_clip_position.w
+    };
+    instances.sort_by(|a, b| ndc_depth(a).total_cmp(&ndc_depth(b)));
+
+    let (emissive_color, emissive_intensity) = material.emissive();
+    let [r, g, b, _] = emissive_color.as_linear_rgba_f32();
+    let emissive_multiplier = Vec3::new(r, g, b) * emissive_intensity;
+    let bubble_radius = material.bubble_radius();
+
+    let viewport = view.size.as_vec2();
+    let clear_color = Vec4::from(clear_color.as_linear_rgba_f32());
+    let mut data = Vec::with_capacity((view.size.x * view.size.y * 4) as usize);
+
+    for y in 0..view.size.y {
+        for x in 0..view.size.x {
+            let frag_coord = Vec2::new(x as f32, y as f32) + 0.5;
+
+            let mut color = clear_color;
+            for instance in &instances {
+                let dist =
+                    bubble_ray_distance(frag_coord, viewport, instance.centroid_clip_position);
+                if dist > bubble_radius * instance.growth {
+                    continue;
+                }
+
+                let mut source = sample(emissive.as_ref(), instance.uv);
+                source = (source.truncate() * emissive_multiplier).extend(source.w);
+                source.w *= instance.growth;
+
+                // BlendState::ALPHA_BLENDING, for both color and alpha
+                color = source * source.w + color * (1.0 - source.w);
+            }
+
+            let color = Color::rgba_linear(color.x, color.y, color.z, color.w);
+            data.extend(
+                color
+                    .as_rgba_f32()
+                    .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8),
+            );
+        }
+    }
+
+    Some(Image::new(
+        Extent3d {
+            width: view.size.x,
+            height: view.size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use bevy::render::mesh::PrimitiveTopology;
+    use bevy::render::texture::{CompressedImageFormats, ImageType};
+
+    use super::*;
+
+    const SIZE: UVec2 = UVec2::new(48, 32);
+    const CLEAR_COLOR: Color = Color::rgb(0.1, 0.1, 0.1);
+
+    /// A camera at the origin looking down -z, with bevy's default kind of projection.
+    fn view() -> ReferenceView {
+        let aspect = SIZE.x as f32 / SIZE.y as f32;
+        ReferenceView {
+            view_proj: Mat4::perspective_infinite_reverse_rh(FRAC_PI_2, aspect, 0.1),
+            size: SIZE,
+        }
+    }
+
+    fn mesh(positions: &[[f32; 3]], uvs: &[[f32; 2]]) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions.to_vec());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 0.0, 1.0]; positions.len()]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs.to_vec());
+        mesh
+    }
+
+    fn triangle() -> Mesh {
+        mesh(
+            &[[-0.5, -0.5, 0.0], [0.5, -0.5, 0.0], [0.0, 0.5, 0.0]],
+            &[[0.0, 0.0], [1.0, 0.0], [0.5, 1.0]],
+        )
+    }
+
+    /// Two triangles leaning away to the right, so their bubbles overlap at different depths.
+    /// The UVs are on texel centers of [`checker`], apart from one in between.
+    fn quad() -> Mesh {
+        let (near_bottom, far_bottom) = ([-1.0, -0.5, -1.5], [1.0, -0.5, -3.0]);
+        let (far_top, near_top) = ([1.0, 0.5, -3.0], [-1.0, 0.5, -1.5]);
+        mesh(
+            &[near_bottom, far_bottom, far_top, near_bottom, far_top, near_top],
+            &[
+                [0.25, 0.25],
+                [0.75, 0.25],
+                [0.75, 0.75],
+                [0.25, 0.25],
+                [0.5, 0.6],
+                [0.25, 0.75],
+            ],
+        )
+    }
+
+    /// One triangle up and to the right, and one behind the camera which shouldn't show up.
+    fn off_centre() -> Mesh {
+        mesh(
+            &[
+                [0.6, 0.3, -1.5],
+                [1.0, 0.3, -1.5],
+                [0.8, 0.7, -1.5],
+                [-0.2, -0.2, 1.0],
+                [0.2, -0.2, 1.0],
+                [0.0, 0.2, 1.0],
+            ],
+            &[[0.0, 0.0]; 6],
+        )
+    }
+
+    /// 2x2 texels of red, half transparent green, blue and mostly transparent white.
+    fn checker() -> Image {
+        Image::new(
+            Extent3d {
+                width: 2,
+                height: 2,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            vec![
+                255, 0, 0, 255, //
+                0, 255, 0, 128, //
+                0, 0, 255, 255, //
+                255, 255, 255, 64,
+            ],
+            TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    fn render(
+        mesh: &Mesh,
+        model: Mat4,
+        material: ReferenceMaterial,
+        emissive: Option<&Image>,
+    ) -> Image {
+        render_bubbles(mesh, model, view(), material, emissive, 200, CLEAR_COLOR)
+            .expect("mesh should render")
+    }
+
+    /// Compare `image` against `tests/golden/{name}.png`, or overwrite the golden image
+    /// instead when `UPDATE_GOLDEN` is set.
+    fn assert_golden(name: &str, image: Image) {
+        let path = format!("{}/tests/golden/{name}.png", env!("CARGO_MANIFEST_DIR"));
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            let image = image.try_into_dynamic().expect("image should convert");
+            image.save(&path).expect("golden image should save");
+            return;
+        }
+
+        let golden = std::fs::read(&path).expect("golden image should exist");
+        let golden = Image::from_buffer(
+            &golden,
+            ImageType::Extension("png"),
+            CompressedImageFormats::NONE,
+            true,
+        )
+        .expect("golden image should load");
+        assert_eq!(golden.size(), image.size(), "{name} is the wrong size");
+
+        // float rounding can flip a few pixels right on the edge of a bubble
+        let mismatched = golden
+            .data
+            .chunks(4)
+            .zip(image.data.chunks(4))
+            .filter(|(a, b)| a.iter().zip(*b).any(|(a, b)| a.abs_diff(*b) > 2))
+            .count();
+        assert!(
+            mismatched <= 4,
+            "{mismatched} pixels differ from {path}, rerun with UPDATE_GOLDEN=1 if that's expected"
+        );
+    }
+
+    #[test]
+    fn triangle_golden() {
+        let bubbles = Bubbles {
+            bubble_radius: 0.3,
+            emissive_color: Color::rgb(1.0, 0.5, 0.25),
+            ..default()
+        };
+        let model = Mat4::from_translation(Vec3::new(0.0, 0.0, -2.0));
+        let image = render(&triangle(), model, ReferenceMaterial::Bubbles(&bubbles), None);
+        assert_golden("triangle", image);
+    }
+
+    #[test]
+    fn quad_golden() {
+        let bubbles = Bubbles {
+            bubble_radius: 0.35,
+            ..default()
+        };
+        let checker = checker();
+        let image = render(
+            &quad(),
+            Mat4::IDENTITY,
+            ReferenceMaterial::Bubbles(&bubbles),
+            Some(&checker),
+        );
+        assert_golden("quad", image);
+    }
+
+    #[test]
+    fn off_centre_golden() {
+        let bubbles = Bubbles {
+            bubble_radius: 0.2,
+            emissive_color: Color::rgb(0.2, 0.6, 1.0),
+            emissive_intensity: 2.0,
+            ..default()
+        };
+        let image = render(
+            &off_centre(),
+            Mat4::IDENTITY,
+            ReferenceMaterial::Bubbles(&bubbles),
+            None,
+        );
+        assert_golden("off_centre", image);
+    }
+
+    #[test]
+    fn teleport_bubbles_grow_with_progress() {
+        let model = Mat4::from_translation(Vec3::new(0.0, 0.0, -2.0));
+        let teleport = |progress| Teleport {
+            bubble_radius: 0.3,
+            emissive_color: Color::rgb(1.0, 0.5, 0.25),
+            progress,
+            ..default()
+        };
+
+        // nothing has dissolved yet, so there are no bubbles, same as not drawing any at all
+        let image = render(&triangle(), model, ReferenceMaterial::Teleport(&teleport(0.0)), None);
+        let mut empty = teleport(0.0);
+        empty.bubble_radius = 0.0;
+        let clear = render(&triangle(), model, ReferenceMaterial::Teleport(&empty), None);
+        assert_eq!(image.data, clear.data);
+
+        // everything has, so they're the same as regular bubbles
+        let image = render(&triangle(), model, ReferenceMaterial::Teleport(&teleport(2.0)), None);
+        assert_golden("triangle", image);
+    }
+
+    #[test]
+    fn dissolve_threshold_in_range() {
+        for i in 0..100 {
+            let position = Vec3::new(i as f32 * 0.37, i as f32 * -0.11, i as f32 * 0.05);
+            let threshold = dissolve_threshold(position, 60.0);
+            assert!((0.0..=1.0).contains(&threshold), "{threshold} at {position}");
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! `noise.wgsl` and `dissolve_threshold`, for the teleport effect's bubbles. This is the 4D
+//! simplex noise from https://github.com/ashima/webgl-noise, line for line with the shader.
+
+use bevy::prelude::*;
+
+fn mod289(x: f32) -> f32 {
+    x - (x * (1.0 / 289.0)).floor() * 289.0
+}
+
+fn mod289_4(x: Vec4) -> Vec4 {
+    x - (x * (1.0 / 289.0)).floor() * 289.0
+}
+
+fn permute(x: f32) -> f32 {
+    mod289(((x * 34.0) + 10.0) * x)
+}
+
+fn permute4(x: Vec4) -> Vec4 {
+    mod289_4(((x * 34.0) + 10.0) * x)
+}
+
+fn taylor_inv_sqrt(r: f32) -> f32 {
+    1.792_842_9 - 0.853_734_7 * r
+}
+
+fn taylor_inv_sqrt4(r: Vec4) -> Vec4 {
+    1.792_842_9 - 0.853_734_7 * r
+}
+
+/// WGSL's `step`
+fn step(edge: f32, x: f32) -> f32 {
+    if x >= edge {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn grad4(j: f32, ip: Vec4) -> Vec4 {
+    // glam's `fract` is `x - floor(x)`, like WGSL's
+    let xyz = ((Vec3::splat(j) * ip.truncate()).fract() * 7.0).floor() * ip.z - 1.0;
+    let p = xyz.extend(1.5 - xyz.abs().dot(Vec3::ONE));
+    let s = Vec4::select(p.cmplt(Vec4::ZERO), Vec4::ONE, Vec4::ZERO);
+    (p.truncate() + (s.truncate() * 2.0 - 1.0) * s.w).extend(p.w)
+}
+
+// (sqrt(5) - 1)/4
+const F4: f32 = 0.309_017;
+
+fn snoise(v: Vec4) -> f32 {
+    let c = Vec4::new(
+        0.138_196_6,  // (5 - sqrt(5))/20  G4
+        0.276_393_2,  // 2 * G4
+        0.414_589_8,  // 3 * G4
+        -0.447_213_6, // -1 + 4 * G4
+    );
+
+    // First corner
+    let mut i = (v + v.dot(Vec4::splat(F4))).floor();
+    let x0 = v - i + i.dot(Vec4::splat(c.x));
+
+    // Other corners, ranked the same way as the shader
+    let is_x = Vec3::new(step(x0.y, x0.x), step(x0.z, x0.x), step(x0.w, x0.x));
+    let is_yz = Vec3::new(step(x0.z, x0.y), step(x0.w, x0.y), step(x0.w, x0.z));
+
+    let minus_x = 1.0 - is_x;
+    let minus_y = 1.0 - is_yz;
+    let i0 = Vec4::new(
+        is_x.x + is_x.y + is_x.z,
+        minus_x.x + is_yz.x + is_yz.y,
+        minus_x.y + minus_y.x + is_yz.z,
+        minus_x.z + minus_y.y + minus_y.z,
+    );
+
+    // i0 now contains the unique values 0,1,2,3 in each channel
+    let i3 = i0.clamp(Vec4::ZERO, Vec4::ONE);
+    let i2 = (i0 - 1.0).clamp(Vec4::ZERO, Vec4::ONE);
+    let i1 = (i0 - 2.0).clamp(Vec4::ZERO, Vec4::ONE);
+
+    let x1 = x0 - i1 + c.x;
+    let x2 = x0 - i2 + c.y;
+    let x3 = x0 - i3 + c.z;
+    let x4 = x0 + c.w;
+
+    // Permutations
+    i = mod289_4(i);
+    let j0 = permute(permute(permute(permute(i.w) + i.z) + i.y) + i.x);
+    let j1 = permute4(i.w + Vec4::new(i1.w, i2.w, i3.w, 1.0));
+    let j1 = permute4(j1 + i.z + Vec4::new(i1.z, i2.z, i3.z, 1.0));
+    let j1 = permute4(j1 + i.y + Vec4::new(i1.y, i2.y, i3.y, 1.0));
+    let j1 = permute4(j1 + i.x + Vec4::new(i1.x, i2.x, i3.x, 1.0));
+
+    // Gradients: 7x7x6 points over a cube, mapped onto a 4-cross polytope
+    let ip = Vec4::new(1.0 / 294.0, 1.0 / 49.0, 1.0 / 7.0, 0.0);
+
+    let mut p0 = grad4(j0, ip);
+    let mut p1 = grad4(j1.x, ip);
+    let mut p2 = grad4(j1.y, ip);
+    let mut p3 = grad4(j1.z, ip);
+    let mut p4 = grad4(j1.w, ip);
+
+    // Normalise gradients
+    let norm = taylor_inv_sqrt4(Vec4::new(p0.dot(p0), p1.dot(p1), p2.dot(p2), p3.dot(p3)));
+    p0 *= norm.x;
+    p1 *= norm.y;
+    p2 *= norm.z;
+    p3 *= norm.w;
+    p4 *= taylor_inv_sqrt(p4.dot(p4));
+
+    // Mix contributions from the five corners
+    let mut m0 = (0.6 - Vec3::new(x0.dot(x0), x1.dot(x1), x2.dot(x2))).max(Vec3::ZERO);
+    let mut m1 = (0.6 - Vec2::new(x3.dot(x3), x4.dot(x4))).max(Vec2::ZERO);
+    m0 = m0 * m0;
+    m1 = m1 * m1;
+    49.0 * ((m0 * m0).dot(Vec3::new(p0.dot(x0), p1.dot(x1), p2.dot(x2)))
+        + (m1 * m1).dot(Vec2::new(p3.dot(x3), p4.dot(x4))))
+}
+
+/// `dissolve_threshold` from `dissolve.wgsl`.
+pub fn dissolve_threshold(local_position: Vec3, noise_scale: f32) -> f32 {
+    (0.5 + 0.5 * snoise((noise_scale * local_position).extend(0.0))).clamp(0.0, 1.0)
+}
@@ -0,0 +1,324 @@
+//! A copy of what each view has drawn, for bubbles which refract the scene behind them.
+//!
+//! Bevy doesn't keep one around, and the main pass draws opaque and transparent things
+//! together, so the bubbles can't sample the view while it's being drawn. Instead the
+//! transparent phase is held back from the main pass, and [`ScreenCopyNode`] blits the main
+//! texture (now only opaque and alpha masked things) into a texture of its own before drawing
+//! the transparent phase itself. Bubbles never see themselves or other transparent things
+//! through the copy, which would otherwise smear them across the screen.
+//!
+//! The copy is only made while some entity has a material which
+//! [reads it](super::BubblesPass::reads_screen_texture).
+
+use bevy::core_pipeline::blit::{BlitPipeline, BlitPipelineKey};
+use bevy::core_pipeline::core_3d::{self, Transparent3d};
+use bevy::prelude::*;
+use bevy::render::camera::ExtractedCamera;
+use bevy::render::render_graph::{
+    Node, NodeRunError, RenderGraph, RenderGraphContext, SlotInfo, SlotType,
+};
+use bevy::render::render_phase::{sort_phase_system, RenderPhase};
+use bevy::render::render_resource::{
+    BindGroupDescriptor, BindGroupEntry, BindingResource, CachedRenderPipelineId, Extent3d, LoadOp,
+    Operations, PipelineCache, RenderPassColorAttachment, RenderPassDepthStencilAttachment,
+    RenderPassDescriptor, RenderPipeline, SpecializedRenderPipelines, Texture, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
+};
+use bevy::render::renderer::{RenderContext, RenderDevice};
+use bevy::render::view::{ViewDepthTexture, ViewTarget};
+use bevy::render::{Extract, RenderApp, RenderSet};
+use bevy::utils::HashMap;
+
+use super::BubblesPass;
+
+/// Sets up the screen copy for every view, shared by all bubbles materials. Each material
+/// still needs [`extract_screen_texture_readers`] to say when it wants the copy.
+pub struct ScreenTexturePlugin;
+
+impl Plugin for ScreenTexturePlugin {
+    fn build(&self, app: &mut App) {
+        let render_app = app.sub_app_mut(RenderApp);
+
+        render_app
+            .init_resource::<ScreenTextures>()
+            .add_system(prepare_screen_textures.in_set(RenderSet::Prepare))
+            .add_system(
+                hold_back_transparent_phase
+                    .in_set(RenderSet::PhaseSort)
+                    .after(sort_phase_system::<Transparent3d>),
+            );
+
+        let node = ScreenCopyNode::new(&mut render_app.world);
+        let mut graph = render_app.world.resource_mut::<RenderGraph>();
+        let Some(core_3d) = graph.get_sub_graph_mut(core_3d::graph::NAME) else { return };
+
+        let input_node = core_3d.input_node().id;
+        core_3d.add_node(ScreenCopyNode::NAME, node);
+        core_3d.add_slot_edge(
+            input_node,
+            core_3d::graph::input::VIEW_ENTITY,
+            ScreenCopyNode::NAME,
+            ScreenCopyNode::IN_VIEW,
+        );
+        core_3d.add_node_edge(core_3d::graph::node::MAIN_PASS, ScreenCopyNode::NAME);
+        core_3d.add_node_edge(ScreenCopyNode::NAME, core_3d::graph::node::TONEMAPPING);
+        // copy the scene before it's bloomed, so it doesn't get bloomed twice
+        if core_3d.get_node_id(core_3d::graph::node::BLOOM).is_ok() {
+            core_3d.add_node_edge(ScreenCopyNode::NAME, core_3d::graph::node::BLOOM);
+        }
+    }
+}
+
+/// Marks the entities (in the render world) whose material needs the screen copy.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct ReadsScreenTexture;
+
+pub fn extract_screen_texture_readers<M: BubblesPass>(
+    mut commands: Commands,
+    mut previous_len: Local<usize>,
+    query: Extract<Query<(Entity, &Handle<M>)>>,
+    materials: Extract<Res<Assets<M>>>,
+) {
+    let mut values = Vec::with_capacity(*previous_len);
+    for (entity, handle) in &query {
+        let Some(material) = materials.get(handle) else { continue };
+        if material.reads_screen_texture() {
+            values.push((entity, ReadsScreenTexture));
+        }
+    }
+
+    *previous_len = values.len();
+    commands.insert_or_spawn_batch(values);
+}
+
+/// The copy of each view, kept from one frame to the next so it's only reallocated when the
+/// view changes.
+#[derive(Resource, Default)]
+struct ScreenTextures(HashMap<Entity, ScreenTexture>);
+
+struct ScreenTexture {
+    size: UVec2,
+    format: TextureFormat,
+    // only kept alive for the view
+    _texture: Texture,
+    view: TextureView,
+}
+
+/// This frame's copy of the view it's on, and the pipeline that makes it.
+#[derive(Component)]
+pub struct ViewScreenTexture {
+    pub view: TextureView,
+    pipeline: CachedRenderPipelineId,
+}
+
+/// The view's transparent phase, taken out of the main pass to be drawn after the copy.
+#[derive(Component, Default)]
+struct HeldBackTransparent(RenderPhase<Transparent3d>);
+
+fn prepare_screen_textures(
+    mut commands: Commands,
+    mut textures: ResMut<ScreenTextures>,
+    render_device: Res<RenderDevice>,
+    pipeline_cache: Res<PipelineCache>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<BlitPipeline>>,
+    blit_pipeline: Res<BlitPipeline>,
+    readers: Query<(), With<ReadsScreenTexture>>,
+    views: Query<(Entity, &ViewTarget, &ExtractedCamera), With<RenderPhase<Transparent3d>>>,
+) {
+    if readers.is_empty() {
+        textures.0.clear();
+        return;
+    }
+
+    let mut previous = std::mem::take(&mut textures.0);
+    for (entity, target, camera) in &views {
+        let Some(size) = camera.physical_target_size else { continue };
+        let format = target.main_texture_format();
+
+        let texture = match previous.remove(&entity) {
+            Some(texture) if texture.size == size && texture.format == format => texture,
+            _ => {
+                let texture = render_device.create_texture(&TextureDescriptor {
+                    label: Some("bubbles screen texture"),
+                    size: Extent3d {
+                        width: size.x,
+                        height: size.y,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format,
+                    usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                });
+                let view = texture.create_view(&TextureViewDescriptor::default());
+                ScreenTexture {
+                    size,
+                    format,
+                    _texture: texture,
+                    view,
+                }
+            }
+        };
+
+        let pipeline = pipelines.specialize(
+            &pipeline_cache,
+            &blit_pipeline,
+            BlitPipelineKey {
+                texture_format: format,
+                blend_state: None,
+                samples: 1,
+            },
+        );
+
+        commands.entity(entity).insert((
+            ViewScreenTexture {
+                view: texture.view.clone(),
+                pipeline,
+            },
+            HeldBackTransparent::default(),
+        ));
+        textures.0.insert(entity, texture);
+    }
+}
+
+/// Empty the main pass's transparent phase on views being copied, once it's sorted. The main
+/// pass skips an empty phase, leaving [`ScreenCopyNode`] to draw it.
+fn hold_back_transparent_phase(
+    mut views: Query<(&mut RenderPhase<Transparent3d>, &mut HeldBackTransparent)>,
+) {
+    for (mut phase, mut held_back) in &mut views {
+        held_back.0.items = std::mem::take(&mut phase.items);
+    }
+}
+
+/// Blits the view's main texture into its [`ViewScreenTexture`] after the main pass, then
+/// draws the transparent phase the main pass was missing.
+pub struct ScreenCopyNode {
+    views: QueryState<(
+        &'static ExtractedCamera,
+        &'static ViewTarget,
+        &'static ViewDepthTexture,
+        &'static ViewScreenTexture,
+        &'static HeldBackTransparent,
+    )>,
+}
+
+impl ScreenCopyNode {
+    pub const NAME: &'static str = "bubbles_screen_copy";
+    pub const IN_VIEW: &'static str = "view";
+
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            views: world.query(),
+        }
+    }
+}
+
+impl Node for ScreenCopyNode {
+    fn input(&self) -> Vec<SlotInfo> {
+        vec![SlotInfo::new(Self::IN_VIEW, SlotType::Entity)]
+    }
+
+    fn update(&mut self, world: &mut World) {
+        self.views.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph.get_input_entity(Self::IN_VIEW)?;
+        let Ok((camera, target, depth, screen_texture, HeldBackTransparent(transparent_phase))) =
+            self.views.get_manual(world, view_entity) else {
+            return Ok(());
+        };
+
+        // still compiling, so just skip the copy until it's ready
+        let pipeline_cache = world.resource::<PipelineCache>();
+        if let Some(pipeline) = pipeline_cache.get_render_pipeline(screen_texture.pipeline) {
+            copy_screen(render_context, pipeline, target, screen_texture, world);
+        }
+
+        if transparent_phase.items.is_empty() {
+            return Ok(());
+        }
+
+        // the same as the main pass would have
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("bubbles_transparent_pass_3d"),
+            color_attachments: &[Some(target.get_color_attachment(Operations {
+                load: LoadOp::Load,
+                store: true,
+            }))],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &depth.view,
+                // store as well, see bevy's main pass
+                depth_ops: Some(Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        if let Some(viewport) = camera.viewport.as_ref() {
+            render_pass.set_camera_viewport(viewport);
+        }
+
+        transparent_phase.render(&mut render_pass, world, view_entity);
+
+        Ok(())
+    }
+}
+
+fn copy_screen(
+    render_context: &mut RenderContext,
+    pipeline: &RenderPipeline,
+    target: &ViewTarget,
+    screen_texture: &ViewScreenTexture,
+    world: &World,
+) {
+    let blit_pipeline = world.resource::<BlitPipeline>();
+
+    let bind_group = render_context
+        .render_device()
+        .create_bind_group(&BindGroupDescriptor {
+            label: Some("bubbles screen copy bind group"),
+            layout: &blit_pipeline.texture_bind_group,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    // the resolved texture when using MSAA
+                    resource: BindingResource::TextureView(target.main_texture()),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&blit_pipeline.sampler),
+                },
+            ],
+        });
+
+    let mut render_pass =
+        render_context
+            .command_encoder()
+            .begin_render_pass(&RenderPassDescriptor {
+                label: Some("bubbles_screen_copy"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &screen_texture.view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Default::default()),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, &bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
@@ -0,0 +1,108 @@
+//! Back-to-front order for the bubbles of each entity, so they blend correctly.
+//!
+//! All of an entity's bubbles are drawn by one instanced draw, which the GPU blends in
+//! instance order. So every frame the bubble centers are found on the CPU, sorted by their
+//! depth in each view, and the order is uploaded for the vertex shader to look up which
+//! bubble each instance should draw (`bubble_instance` in `bubble_functions.wgsl`).
+//!
+//! The centers are the triangle centroids of [`PosedMeshes`], whose vertices are de-indexed
+//! the same way the storage buffer is, so every three instances are one of the mesh's faces.
+
+use std::marker::PhantomData;
+
+use bevy::core_pipeline::core_3d::Transparent3d;
+use bevy::prelude::*;
+use bevy::render::render_phase::RenderPhase;
+use bevy::render::render_resource::{BufferInitDescriptor, BufferUsages};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::view::ExtractedView;
+use bevy::utils::{HashMap, HashSet};
+
+use super::BubblesPass;
+use crate::mesh_storage::{PosedMeshes, ReusableBuffer, SizedBuffer};
+use crate::settings::EffectSettings;
+
+/// The draw order of every entity drawn as `M` in this view, as an `array<u32>` of instance
+/// indices.
+#[derive(Component)]
+pub struct ViewBubbleOrder<M> {
+    pub orders: HashMap<Entity, SizedBuffer>,
+    marker: PhantomData<M>,
+}
+
+/// The draw order for views that don't sort. It only has the first instance, and
+/// `bubble_instance` leaves everything past the end of the order alone.
+pub fn unsorted_order(render_device: &RenderDevice) -> SizedBuffer {
+    SizedBuffer::from(render_device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("unsorted bubble order"),
+        contents: bytemuck::cast_slice(&[0_u32]),
+        usage: BufferUsages::STORAGE,
+    }))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn prepare_bubble_order<M: BubblesPass>(
+    mut commands: Commands,
+    settings: Res<EffectSettings>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    posed: Res<PosedMeshes>,
+    // by view and entity, kept between frames
+    mut buffers: Local<HashMap<(Entity, Entity), ReusableBuffer>>,
+    views: Query<(Entity, &ExtractedView), With<RenderPhase<Transparent3d>>>,
+    bubbles: Query<Entity, With<Handle<M>>>,
+) {
+    if !settings.sort_bubbles {
+        buffers.clear();
+        return;
+    }
+
+    let mut written = HashSet::default();
+    for (view_entity, view) in &views {
+        let world_to_view = view.transform.compute_matrix().inverse();
+
+        let mut orders = HashMap::default();
+        for entity in &bubbles {
+            // invalid meshes were already reported
+            let Some(posed) = posed.get(entity) else { continue };
+            let instance_count = posed.instance_count(settings.max_bubbles) as usize;
+
+            // once per triangle, since all three of its bubbles are in the same place. The
+            // camera looks down -z, so the furthest bubbles have the lowest z
+            let mut triangles: Vec<(f32, u32)> = posed
+                .centroids
+                .iter()
+                .take((instance_count + 2) / 3)
+                .enumerate()
+                .map(|(i, centroid)| (world_to_view.transform_point3(*centroid).z, i as u32))
+                .collect();
+            // stable, so ties are drawn in mesh order like the reference renderer
+            triangles.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let order: Vec<u32> = triangles
+                .iter()
+                .flat_map(|&(_, triangle)| triangle * 3..triangle * 3 + 3)
+                .filter(|&instance| (instance as usize) < instance_count)
+                .collect();
+            // storage buffers can't be empty
+            if order.is_empty() {
+                continue;
+            }
+
+            let buffer = buffers
+                .entry((view_entity, entity))
+                .or_insert_with(|| ReusableBuffer::new("bubble order", BufferUsages::STORAGE))
+                .write(&render_device, &render_queue, bytemuck::cast_slice(&order));
+            orders.insert(entity, buffer);
+            written.insert((view_entity, entity));
+        }
+
+        commands.entity(view_entity).insert(ViewBubbleOrder::<M> {
+            orders,
+            marker: PhantomData,
+        });
+    }
+
+    // views and entities that have gone
+    buffers.retain(|key, _| written.contains(key));
+}
@@ -0,0 +1,310 @@
+//! The `@group(3)` bindings of the bubbles pipelines, which are different in every view: the
+//! metaball bins, the screen texture, the bubbles' draw order and the camera their radii are
+//! measured in. Only four bind groups are guaranteed, so they share one, and whichever isn't
+//! in use gets a stand-in.
+//!
+//! Bubble radii are in half viewport heights of the camera, which is the view itself except
+//! in a light's view. There the prepass shader uses the camera to find how big the bubble is
+//! in world space, so its shadow matches what the camera sees.
+
+use std::marker::PhantomData;
+
+use bevy::core::{Pod, Zeroable};
+use bevy::core_pipeline::core_3d::Transparent3d;
+use bevy::ecs::query::ROQueryItem;
+use bevy::ecs::system::lifetimeless::*;
+use bevy::ecs::system::SystemParamItem;
+use bevy::pbr::Shadow;
+use bevy::prelude::*;
+use bevy::render::camera::ExtractedCamera;
+use bevy::render::render_phase::{
+    PhaseItem, RenderCommand, RenderCommandResult, RenderPhase, TrackedRenderPass,
+};
+use bevy::render::render_resource::{
+    AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+    BufferBindingType, BufferUsages, FilterMode, Sampler, SamplerBindingType, SamplerDescriptor,
+    ShaderStages, TextureSampleType, TextureView, TextureViewDimension,
+};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::texture::FallbackImage;
+use bevy::render::view::ExtractedView;
+use bevy::utils::HashMap;
+
+use super::metaballs::{MetaballBins, ViewMetaballBins};
+use super::screen_texture::ViewScreenTexture;
+use super::sorting::{unsorted_order, ViewBubbleOrder};
+use super::BubblesPass;
+use crate::mesh_storage::{ReusableBuffer, SizedBuffer};
+
+/// Layout of the `@group(3)` bindings, shared by every bubbles material.
+#[derive(Resource)]
+pub struct BubblesViewLayout {
+    pub layout: BindGroupLayout,
+    screen_sampler: Sampler,
+    /// Bound when metaballs are off
+    empty_bins: MetaballBins,
+    /// Bound when the bubbles aren't sorted
+    unsorted_order: SizedBuffer,
+}
+
+impl FromWorld for BubblesViewLayout {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let buffer = |binding, ty| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::VERTEX_FRAGMENT,
+            ty: BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage = BufferBindingType::Storage { read_only: true };
+
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("bubbles view layout"),
+            entries: &[
+                // metaball bins
+                buffer(0, BufferBindingType::Uniform),
+                buffer(1, storage),
+                buffer(2, storage),
+                // screen texture
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                // draw order
+                buffer(5, storage),
+                // bubble camera
+                buffer(6, BufferBindingType::Uniform),
+            ],
+        });
+
+        let screen_sampler = render_device.create_sampler(&SamplerDescriptor {
+            label: Some("bubbles screen sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
+
+        Self {
+            layout,
+            screen_sampler,
+            empty_bins: MetaballBins::empty(render_device),
+            unsorted_order: unsorted_order(render_device),
+        }
+    }
+}
+
+/// `BubbleCamera` in `bubble_functions.wgsl`.
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct BubbleCameraUniform {
+    view_proj: Mat4,
+    projection_scale: f32,
+    _padding: [f32; 3],
+}
+
+/// The camera's own `BubbleCamera`.
+#[derive(Component)]
+pub struct ViewBubbleCamera(SizedBuffer);
+
+/// The `BubbleCamera` for shadow maps, which aren't drawn for any one camera. Bubbles are
+/// sized for the first camera to be rendered, usually the only one.
+#[derive(Resource, Default)]
+pub struct ShadowBubbleCamera(Option<SizedBuffer>);
+
+pub fn prepare_bubble_cameras(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut shadow_camera: ResMut<ShadowBubbleCamera>,
+    // kept between frames
+    mut buffers: Local<HashMap<Entity, ReusableBuffer>>,
+    views: Query<(Entity, &ExtractedView, &ExtractedCamera), With<RenderPhase<Transparent3d>>>,
+) {
+    buffers.retain(|view_entity, _| views.contains(*view_entity));
+
+    let mut cameras = Vec::new();
+    for (view_entity, view, camera) in &views {
+        let view_proj = view.view_projection.unwrap_or_else(|| {
+            view.projection * view.transform.compute_matrix().inverse()
+        });
+        let uniform = BubbleCameraUniform {
+            view_proj,
+            projection_scale: view.projection.y_axis.y,
+            _padding: [0.0; 3],
+        };
+
+        let buffer = buffers
+            .entry(view_entity)
+            .or_insert_with(|| ReusableBuffer::new("bubble camera", BufferUsages::UNIFORM))
+            .write(&render_device, &render_queue, bytemuck::bytes_of(&uniform));
+
+        cameras.push((camera.order, buffer.clone()));
+        commands.entity(view_entity).insert(ViewBubbleCamera(buffer));
+    }
+
+    shadow_camera.0 = cameras
+        .into_iter()
+        .min_by_key(|(order, _)| *order)
+        .map(|(_, buffer)| buffer);
+}
+
+/// The `@group(3)` bind groups for every entity drawn as `M` in this view, which can also be
+/// a light's view when drawing shadows.
+#[derive(Component)]
+pub struct ViewBubblesBindGroups<M> {
+    /// For entities without metaball bins or a draw order
+    shared: BindGroup,
+    entities: HashMap<Entity, BindGroup>,
+    marker: PhantomData<M>,
+}
+
+pub fn queue_bubbles_view_bind_groups<M: BubblesPass>(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    layout: Res<BubblesViewLayout>,
+    fallback_image: Res<FallbackImage>,
+    shadow_camera: Res<ShadowBubbleCamera>,
+    views: Query<
+        (
+            Entity,
+            Option<&ViewMetaballBins<M>>,
+            Option<&ViewBubbleOrder<M>>,
+            Option<&ViewScreenTexture>,
+            Option<&ViewBubbleCamera>,
+        ),
+        Or<(With<RenderPhase<Transparent3d>>, With<RenderPhase<Shadow>>)>,
+    >,
+) {
+    for (view_entity, metaball_bins, orders, screen_texture, camera) in &views {
+        // only missing for shadows when there aren't any cameras, so nothing would be drawn
+        let Some(camera) = camera.map(|camera| &camera.0).or(shadow_camera.0.as_ref()) else {
+            continue;
+        };
+        // nothing reads the screen texture when there isn't one
+        let screen_texture = screen_texture
+            .map(|screen_texture| &screen_texture.view)
+            .unwrap_or(&fallback_image.texture_view);
+        let bind_group = |bins: Option<&MetaballBins>, order: Option<&SizedBuffer>| {
+            create_bind_group(
+                &render_device,
+                &layout,
+                bins.unwrap_or(&layout.empty_bins),
+                order.unwrap_or(&layout.unsorted_order),
+                screen_texture,
+                camera,
+            )
+        };
+
+        let bins = |entity: &Entity| metaball_bins.and_then(|view_bins| view_bins.bins.get(entity));
+        let order = |entity: &Entity| orders.and_then(|orders| orders.orders.get(entity));
+
+        let mut entities = HashMap::default();
+        let bins_entities = metaball_bins.into_iter().flat_map(|view_bins| view_bins.bins.keys());
+        let order_entities = orders.into_iter().flat_map(|orders| orders.orders.keys());
+        for entity in bins_entities.chain(order_entities) {
+            entities
+                .entry(*entity)
+                .or_insert_with(|| bind_group(bins(entity), order(entity)));
+        }
+
+        commands.entity(view_entity).insert(ViewBubblesBindGroups::<M> {
+            shared: bind_group(None, None),
+            entities,
+            marker: PhantomData,
+        });
+    }
+}
+
+fn create_bind_group(
+    render_device: &RenderDevice,
+    layout: &BubblesViewLayout,
+    bins: &MetaballBins,
+    order: &SizedBuffer,
+    screen_texture: &TextureView,
+    camera: &SizedBuffer,
+) -> BindGroup {
+    render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("bubbles view bind group"),
+        layout: &layout.layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: bins.uniform.binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: bins.ranges.binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: bins.entries.binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::TextureView(screen_texture),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: BindingResource::Sampler(&layout.screen_sampler),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: order.binding(),
+            },
+            BindGroupEntry {
+                binding: 6,
+                resource: camera.binding(),
+            },
+        ],
+    })
+}
+
+/// Binds the entity's [`ViewBubblesBindGroups`] at `I`.
+pub struct SetBubblesViewBindGroup<M, const I: usize>(PhantomData<M>);
+
+impl<P: PhaseItem, M: BubblesPass, const I: usize> RenderCommand<P>
+    for SetBubblesViewBindGroup<M, I>
+{
+    type Param = ();
+
+    type ViewWorldQuery = Option<Read<ViewBubblesBindGroups<M>>>;
+
+    type ItemWorldQuery = ();
+
+    fn render<'w>(
+        item: &P,
+        bind_groups: ROQueryItem<'w, Self::ViewWorldQuery>,
+        _entity: (),
+        _param: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(bind_groups) = bind_groups else { return RenderCommandResult::Failure };
+
+        let bind_group = bind_groups
+            .entities
+            .get(&item.entity())
+            .unwrap_or(&bind_groups.shared);
+        pass.set_bind_group(I, bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
@@ -0,0 +1,149 @@
+//! A camera that orbits around a focus point, to look at the effect from any side.
+//!
+//! Mouse: drag with the left button to orbit, the right or middle button to pan, and scroll
+//! to zoom. Keyboard: `W`/`A`/`S`/`D` orbit, `Q`/`E` zoom and `R` resets the view.
+
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+
+pub struct OrbitCameraPlugin;
+
+impl Plugin for OrbitCameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(orbit_camera_input)
+            .add_system(update_orbit_camera.after(orbit_camera_input));
+    }
+}
+
+/// Keeps the camera's [`Transform`] pointed at `focus`, from `radius` away.
+#[derive(Component, Debug, Clone)]
+pub struct OrbitCamera {
+    pub focus: Vec3,
+    pub radius: f32,
+    /// Rotation around the Y axis, in radians
+    pub yaw: f32,
+    /// Angle above the horizon, in radians
+    pub pitch: f32,
+
+    /// Where to go back to when resetting the view
+    initial: (Vec3, f32, f32, f32),
+}
+
+// stop just short of straight up or down, where the camera's up vector flips
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+const MIN_RADIUS: f32 = 0.05;
+
+/// Radians per pixel of mouse movement
+const MOUSE_ORBIT_SPEED: f32 = 0.005;
+/// Fraction of the distance to the focus per pixel of mouse movement
+const PAN_SPEED: f32 = 0.002;
+/// Radians per second
+const KEY_ORBIT_SPEED: f32 = 1.5;
+/// Fraction of the distance to the focus, per line scrolled (or per tenth of a second of
+/// holding a key)
+const ZOOM_SPEED: f32 = 0.1;
+
+impl OrbitCamera {
+    pub fn looking_at(position: Vec3, focus: Vec3) -> Self {
+        let offset = position - focus;
+        let radius = offset.length().max(MIN_RADIUS);
+        let yaw = offset.x.atan2(offset.z);
+        let pitch = (offset.y / radius).clamp(-1.0, 1.0).asin();
+
+        Self {
+            focus,
+            radius,
+            yaw,
+            pitch,
+            initial: (focus, radius, yaw, pitch),
+        }
+    }
+
+    /// Look at something `size` across from a distance where it fits on screen, and make
+    /// that the view to reset to.
+    pub fn frame(&mut self, center: Vec3, size: f32) {
+        self.focus = center;
+        self.radius = (size * 1.2).max(MIN_RADIUS);
+        self.initial = (self.focus, self.radius, self.yaw, self.pitch);
+    }
+
+    pub fn reset(&mut self) {
+        (self.focus, self.radius, self.yaw, self.pitch) = self.initial;
+    }
+
+    pub fn transform(&self) -> Transform {
+        let offset = Vec3::new(
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.cos(),
+        );
+
+        Transform::from_translation(self.focus + self.radius * offset)
+            .looking_at(self.focus, Vec3::Y)
+    }
+
+    fn orbit(&mut self, delta: Vec2) {
+        self.yaw -= delta.x;
+        self.pitch = (self.pitch + delta.y).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    fn zoom(&mut self, amount: f32) {
+        self.radius = (self.radius * (1.0 - amount)).max(MIN_RADIUS);
+    }
+}
+
+fn orbit_camera_input(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<MouseButton>>,
+    mut motion: EventReader<MouseMotion>,
+    mut scroll: EventReader<MouseWheel>,
+    mut cameras: Query<(&mut OrbitCamera, &Transform)>,
+) {
+    let mouse_delta: Vec2 = motion.iter().map(|event| event.delta).sum();
+    let scrolled: f32 = scroll
+        .iter()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            // roughly one line's worth of pixels
+            MouseScrollUnit::Pixel => event.y / 16.0,
+        })
+        .sum();
+
+    let key_axis = |negative, positive| {
+        let pressed = |key| if keys.pressed(key) { 1.0 } else { 0.0 };
+        pressed(positive) - pressed(negative)
+    };
+    let key_orbit = Vec2::new(
+        key_axis(KeyCode::A, KeyCode::D),
+        key_axis(KeyCode::S, KeyCode::W),
+    );
+    let key_zoom = key_axis(KeyCode::Q, KeyCode::E);
+
+    for (mut camera, transform) in &mut cameras {
+        if keys.just_pressed(KeyCode::R) {
+            camera.reset();
+        }
+
+        if buttons.pressed(MouseButton::Left) {
+            camera.orbit(mouse_delta * MOUSE_ORBIT_SPEED);
+        }
+
+        if buttons.any_pressed([MouseButton::Right, MouseButton::Middle]) {
+            // move the focus with the cursor, scaled so it keeps up at any zoom
+            let pan = transform.left() * mouse_delta.x + transform.up() * mouse_delta.y;
+            camera.focus += pan * camera.radius * PAN_SPEED;
+        }
+
+        camera.orbit(key_orbit * KEY_ORBIT_SPEED * time.delta_seconds());
+        camera.zoom((scrolled + key_zoom * 10.0 * time.delta_seconds()) * ZOOM_SPEED);
+    }
+}
+
+fn update_orbit_camera(mut cameras: Query<(&OrbitCamera, &mut Transform)>) {
+    for (camera, mut transform) in &mut cameras {
+        *transform = camera.transform();
+    }
+}
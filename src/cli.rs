@@ -0,0 +1,432 @@
+//! Command-line options for the viewer. Parsing is kept separate from the app, so it works on
+//! any list of arguments rather than just the real ones.
+
+use std::fmt;
+
+use bevy::prelude::*;
+
+use crate::bubbles::{BubbleShading, ColorSource};
+use crate::lighting::LightingPreset;
+
+pub const USAGE: &str = "\
+Usage: mario-particles [OPTIONS]
+
+Options:
+  --model <PATH>          glTF file to load, relative to the assets folder
+                          [default: colette/Colette.gltf]
+  --scene <INDEX>         which scene in the glTF file to show [default: 0]
+  --size <WIDTHxHEIGHT>   window size [default: 800x600]
+  --msaa <SAMPLES>        1 (off), 2, 4 or 8 [default: 8]
+  --background <HEX>      clear color, e.g. 808080 or #ff000080 [default: 808080]
+  --camera <X,Y,Z>        initial camera position [default: 2,1.5,-2]
+  --look-at <X,Y,Z>       point the camera looks at [default: 0,0.75,0]
+  --effect <EFFECT>       teleport, noisy or bubbles [default: teleport]
+  --lighting <PRESET>     three-point or key-light [default: three-point]
+  --bubble-color <SOURCE> base-color, emissive, vertex-color, tint or gradient, for the
+                          bubbles effect [default: emissive]
+  --bubble-shading <MODE> emissive or water, for the bubbles effect [default: emissive]
+  --no-rotate             don't spin the model
+  --hdr                   render in HDR with bloom, so bright bubbles glow
+  -h, --help              print this message
+";
+
+/// Everything configurable from the command line.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct Args {
+    pub model: String,
+    pub scene: usize,
+    pub window_size: Vec2,
+    /// Number of MSAA samples, see [`Args::msaa`]
+    pub msaa_samples: u32,
+    pub background: Color,
+    pub camera_position: Vec3,
+    pub camera_target: Vec3,
+    pub effect: Effect,
+    pub lighting: LightingPreset,
+    pub bubble_color: ColorSource,
+    pub bubble_shading: BubbleShading,
+    pub rotate: bool,
+    pub hdr: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            model: "colette/Colette.gltf".to_string(),
+            scene: 0,
+            window_size: Vec2::new(800.0, 600.0),
+            msaa_samples: 8,
+            background: Color::GRAY,
+            camera_position: Vec3::new(2.0, 1.5, -2.0),
+            camera_target: Vec3::new(0.0, 0.75, 0.0),
+            effect: Effect::Teleport,
+            lighting: LightingPreset::ThreePoint,
+            bubble_color: ColorSource::Emissive,
+            bubble_shading: BubbleShading::Emissive,
+            rotate: true,
+            hdr: false,
+        }
+    }
+}
+
+/// Which material the model starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Effect {
+    #[default]
+    Teleport,
+    Noisy,
+    Bubbles,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgsError {
+    /// `--help` was passed, so print the usage instead of running
+    Help,
+    UnknownFlag(String),
+    MissingValue(&'static str),
+    InvalidValue { flag: &'static str, value: String },
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::Help => write!(f, "help requested"),
+            ArgsError::UnknownFlag(flag) => write!(f, "unknown option `{flag}`"),
+            ArgsError::MissingValue(flag) => write!(f, "`{flag}` needs a value"),
+            ArgsError::InvalidValue { flag, value } => {
+                write!(f, "invalid value `{value}` for `{flag}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+impl Args {
+    /// Parse the options, not including the program name. Values can either follow their
+    /// flag or be joined to it with `=`, e.g. `--msaa 4` or `--msaa=4`.
+    pub fn parse<I, S>(args: I) -> Result<Self, ArgsError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter().map(Into::into);
+
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+
+            // flags without values
+            match flag.as_str() {
+                "-h" | "--help" => return Err(ArgsError::Help),
+                "--no-rotate" => {
+                    parsed.rotate = false;
+                    continue;
+                }
+                "--hdr" => {
+                    parsed.hdr = true;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let flag: &'static str = match flag.as_str() {
+                "--model" => "--model",
+                "--scene" => "--scene",
+                "--size" => "--size",
+                "--msaa" => "--msaa",
+                "--background" => "--background",
+                "--camera" => "--camera",
+                "--look-at" => "--look-at",
+                "--effect" => "--effect",
+                "--lighting" => "--lighting",
+                "--bubble-color" => "--bubble-color",
+                "--bubble-shading" => "--bubble-shading",
+                _ => return Err(ArgsError::UnknownFlag(flag)),
+            };
+
+            let Some(value) = inline_value.or_else(|| args.next())
+            else {
+                return Err(ArgsError::MissingValue(flag));
+            };
+            let invalid = || ArgsError::InvalidValue {
+                flag,
+                value: value.clone(),
+            };
+
+            match flag {
+                "--model" => parsed.model = value.clone(),
+                "--scene" => parsed.scene = value.parse().map_err(|_| invalid())?,
+                "--size" => parsed.window_size = parse_size(&value).ok_or_else(invalid)?,
+                "--msaa" => {
+                    parsed.msaa_samples = match value.as_str() {
+                        "1" | "2" | "4" | "8" => value.parse().map_err(|_| invalid())?,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "--background" => parsed.background = Color::hex(&value).map_err(|_| invalid())?,
+                "--camera" => parsed.camera_position = parse_vec3(&value).ok_or_else(invalid)?,
+                "--look-at" => parsed.camera_target = parse_vec3(&value).ok_or_else(invalid)?,
+                "--effect" => {
+                    parsed.effect = match value.as_str() {
+                        "teleport" => Effect::Teleport,
+                        "noisy" => Effect::Noisy,
+                        "bubbles" => Effect::Bubbles,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "--lighting" => {
+                    parsed.lighting = match value.as_str() {
+                        "three-point" => LightingPreset::ThreePoint,
+                        "key-light" => LightingPreset::KeyLight,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "--bubble-color" => {
+                    parsed.bubble_color = match value.as_str() {
+                        "base-color" => ColorSource::BaseColor,
+                        "emissive" => ColorSource::Emissive,
+                        "vertex-color" => ColorSource::VertexColor,
+                        "tint" => ColorSource::Tint,
+                        "gradient" => ColorSource::Gradient,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "--bubble-shading" => {
+                    parsed.bubble_shading = match value.as_str() {
+                        "emissive" => BubbleShading::Emissive,
+                        "water" => BubbleShading::Water,
+                        _ => return Err(invalid()),
+                    }
+                }
+                _ => unreachable!("flag was checked above"),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// The asset path of the scene to show, e.g. `colette/Colette.gltf#Scene0`.
+    pub fn scene_path(&self) -> String {
+        format!("{}#Scene{}", self.model, self.scene)
+    }
+
+    pub fn msaa(&self) -> Msaa {
+        match self.msaa_samples {
+            1 => Msaa::Off,
+            2 => Msaa::Sample2,
+            4 => Msaa::Sample4,
+            _ => Msaa::Sample8,
+        }
+    }
+}
+
+/// Parse `WIDTHxHEIGHT`, e.g. `1280x720`.
+fn parse_size(value: &str) -> Option<Vec2> {
+    let (width, height) = value.split_once('x')?;
+    let size = Vec2::new(width.trim().parse().ok()?, height.trim().parse().ok()?);
+    size.cmpgt(Vec2::ZERO).all().then_some(size)
+}
+
+/// Parse `X,Y,Z`, e.g. `2,1.5,-2`.
+fn parse_vec3(value: &str) -> Option<Vec3> {
+    let mut components = value.split(',').map(|c| c.trim().parse::<f32>());
+    let vec = Vec3::new(
+        components.next()?.ok()?,
+        components.next()?.ok()?,
+        components.next()?.ok()?,
+    );
+    components.next().is_none().then_some(vec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, ArgsError> {
+        Args::parse(args.iter().copied())
+    }
+
+    fn invalid(flag: &'static str, value: &str) -> Result<Args, ArgsError> {
+        Err(ArgsError::InvalidValue {
+            flag,
+            value: value.to_string(),
+        })
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(parse(&[]), Ok(Args::default()));
+        assert_eq!(Args::default().scene_path(), "colette/Colette.gltf#Scene0");
+        // Msaa isn't Debug
+        assert!(Args::default().msaa() == Msaa::Sample8);
+    }
+
+    #[test]
+    fn every_flag() {
+        let args = parse(&[
+            "--model",
+            "fox/Fox.glb",
+            "--scene",
+            "2",
+            "--size",
+            "1280x720",
+            "--msaa",
+            "4",
+            "--background",
+            "#ff000080",
+            "--camera",
+            "1, 2.5,-3",
+            "--look-at",
+            "0,1,0",
+            "--effect",
+            "bubbles",
+            "--lighting",
+            "key-light",
+            "--bubble-color",
+            "gradient",
+            "--bubble-shading",
+            "water",
+            "--no-rotate",
+            "--hdr",
+        ]);
+
+        let expected = Args {
+            model: "fox/Fox.glb".to_string(),
+            scene: 2,
+            window_size: Vec2::new(1280.0, 720.0),
+            msaa_samples: 4,
+            background: Color::rgba_u8(255, 0, 0, 128),
+            camera_position: Vec3::new(1.0, 2.5, -3.0),
+            camera_target: Vec3::new(0.0, 1.0, 0.0),
+            effect: Effect::Bubbles,
+            lighting: LightingPreset::KeyLight,
+            bubble_color: ColorSource::Gradient,
+            bubble_shading: BubbleShading::Water,
+            rotate: false,
+            hdr: true,
+        };
+        assert_eq!(args, Ok(expected));
+        assert_eq!(args.unwrap().scene_path(), "fox/Fox.glb#Scene2");
+    }
+
+    #[test]
+    fn enum_values() {
+        let effects = [
+            ("teleport", Effect::Teleport),
+            ("noisy", Effect::Noisy),
+            ("bubbles", Effect::Bubbles),
+        ];
+        for (value, effect) in effects {
+            assert_eq!(parse(&["--effect", value]).unwrap().effect, effect);
+        }
+
+        let presets = [
+            ("three-point", LightingPreset::ThreePoint),
+            ("key-light", LightingPreset::KeyLight),
+        ];
+        for (value, preset) in presets {
+            assert_eq!(parse(&["--lighting", value]).unwrap().lighting, preset);
+        }
+
+        let sources = [
+            ("base-color", ColorSource::BaseColor),
+            ("emissive", ColorSource::Emissive),
+            ("vertex-color", ColorSource::VertexColor),
+            ("tint", ColorSource::Tint),
+            ("gradient", ColorSource::Gradient),
+        ];
+        for (value, source) in sources {
+            assert_eq!(parse(&["--bubble-color", value]).unwrap().bubble_color, source);
+        }
+
+        let shadings = [
+            ("emissive", BubbleShading::Emissive),
+            ("water", BubbleShading::Water),
+        ];
+        for (value, shading) in shadings {
+            assert_eq!(parse(&["--bubble-shading", value]).unwrap().bubble_shading, shading);
+        }
+    }
+
+    #[test]
+    fn msaa_samples() {
+        let cases = [
+            ("1", Msaa::Off),
+            ("2", Msaa::Sample2),
+            ("4", Msaa::Sample4),
+            ("8", Msaa::Sample8),
+        ];
+        for (value, msaa) in cases {
+            assert!(parse(&["--msaa", value]).unwrap().msaa() == msaa, "--msaa {value}");
+        }
+
+        for value in ["0", "3", "16", "four", "-4"] {
+            assert_eq!(parse(&["--msaa", value]), invalid("--msaa", value));
+        }
+    }
+
+    #[test]
+    fn inline_values() {
+        assert_eq!(
+            parse(&["--msaa=2", "--camera=1,2,3", "--model=a=b.gltf"]),
+            parse(&["--msaa", "2", "--camera", "1,2,3", "--model", "a=b.gltf"]),
+        );
+        // an empty inline value is still a value, rather than taking the next argument
+        assert_eq!(parse(&["--model=", "--hdr"]).unwrap().model, "");
+        assert_eq!(parse(&["--scene=", "1"]), invalid("--scene", ""));
+    }
+
+    #[test]
+    fn later_flags_win() {
+        let args = parse(&["--effect", "noisy", "--effect=bubbles"]).unwrap();
+        assert_eq!(args.effect, Effect::Bubbles);
+    }
+
+    #[test]
+    fn unknown_flags() {
+        assert_eq!(parse(&["--mdoel", "x"]), Err(ArgsError::UnknownFlag("--mdoel".into())));
+        assert_eq!(parse(&["model.gltf"]), Err(ArgsError::UnknownFlag("model.gltf".into())));
+    }
+
+    #[test]
+    fn missing_values() {
+        assert_eq!(parse(&["--model"]), Err(ArgsError::MissingValue("--model")));
+        assert_eq!(
+            parse(&["--hdr", "--bubble-shading"]),
+            Err(ArgsError::MissingValue("--bubble-shading"))
+        );
+    }
+
+    #[test]
+    fn malformed_vectors() {
+        for value in ["1,2", "1,2,3,4", "1,,3", "a,b,c", "", "1;2;3"] {
+            assert_eq!(parse(&["--camera", value]), invalid("--camera", value));
+            assert_eq!(parse(&["--look-at", value]), invalid("--look-at", value));
+        }
+    }
+
+    #[test]
+    fn malformed_values() {
+        for value in ["800", "800x", "0x600", "-800x600", "800by600"] {
+            assert_eq!(parse(&["--size", value]), invalid("--size", value));
+        }
+        assert_eq!(parse(&["--scene", "-1"]), invalid("--scene", "-1"));
+        assert_eq!(parse(&["--background", "grey"]), invalid("--background", "grey"));
+        assert_eq!(parse(&["--effect", "Teleport"]), invalid("--effect", "Teleport"));
+        assert_eq!(parse(&["--lighting", "studio"]), invalid("--lighting", "studio"));
+        assert_eq!(parse(&["--bubble-color", "red"]), invalid("--bubble-color", "red"));
+        assert_eq!(parse(&["--bubble-shading", "glass"]), invalid("--bubble-shading", "glass"));
+    }
+
+    #[test]
+    fn help() {
+        assert_eq!(parse(&["--help"]), Err(ArgsError::Help));
+        assert_eq!(parse(&["-h"]), Err(ArgsError::Help));
+        // wherever it is, and even if the rest is wrong
+        assert_eq!(parse(&["--msaa", "4", "-h", "--bogus"]), Err(ArgsError::Help));
+    }
+}
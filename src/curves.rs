@@ -0,0 +1,354 @@
+//! Keyframed curves to animate the material uniforms over the course of the effect, e.g.
+//! to ramp the noise up before bursting, or swell the bubbles and shrink them again.
+//!
+//! Any uniform without a curve just uses its value from the [`EffectSettings`].
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::Deserialize;
+
+use crate::settings::EffectSettings;
+
+pub struct ParameterCurvesPlugin;
+
+impl Plugin for ParameterCurvesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ParameterCurves>()
+            .init_resource::<AnimatedUniforms>()
+            // before anything in `Update` copies the values into materials
+            .add_system(evaluate_curves.in_base_set(CoreSet::PreUpdate));
+    }
+}
+
+/// How to get from one keyframe's value to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    /// A cubic bezier through (0, 0) and (1, 1) with these two control points, the same as
+    /// CSS's `cubic-bezier(x1, y1, x2, y2)`. The x coordinates should be within [0, 1].
+    Bezier(Vec2, Vec2),
+    /// Hold this keyframe's value until the next keyframe.
+    Step,
+}
+
+impl Easing {
+    /// Map a fraction of the way between two keyframes, from 0 to 1, to how far the value
+    /// should have changed (also from 0 to 1, although beziers may overshoot).
+    pub fn ease(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            Easing::Bezier(p1, p2) => cubic_bezier(p1, p2, t),
+            Easing::Step => 0.0,
+        }
+    }
+}
+
+/// Evaluate a CSS-style cubic bezier easing at `x`. The curve is parametric, so first solve
+/// for the parameter that gives `x`, then use that to find `y`.
+fn cubic_bezier(p1: Vec2, p2: Vec2, x: f32) -> f32 {
+    // one dimension of a bezier from 0 to 1, with control points `a` and `b`
+    fn bezier(a: f32, b: f32, t: f32) -> f32 {
+        let u = 1.0 - t;
+        3.0 * u * u * t * a + 3.0 * u * t * t * b + t * t * t
+    }
+
+    fn bezier_slope(a: f32, b: f32, t: f32) -> f32 {
+        let u = 1.0 - t;
+        3.0 * u * u * a + 6.0 * u * t * (b - a) + 3.0 * t * t * (1.0 - b)
+    }
+
+    // Newton's method converges quickly for most curves...
+    let mut t = x;
+    for _ in 0..8 {
+        let error = bezier(p1.x, p2.x, t) - x;
+        if error.abs() < 1e-5 {
+            return bezier(p1.y, p2.y, t);
+        }
+
+        let slope = bezier_slope(p1.x, p2.x, t);
+        if slope.abs() < 1e-6 {
+            break;
+        }
+        t -= error / slope;
+    }
+
+    // ...but falls over on flat spots, so fall back to bisection. x is monotonic in t as
+    // long as the control points' x coordinates are within [0, 1].
+    let (mut low, mut high) = (0.0, 1.0);
+    t = x;
+    for _ in 0..32 {
+        let error = bezier(p1.x, p2.x, t) - x;
+        if error.abs() < 1e-5 {
+            break;
+        }
+        if error > 0.0 {
+            high = t;
+        } else {
+            low = t;
+        }
+        t = (low + high) / 2.0;
+    }
+
+    bezier(p1.y, p2.y, t)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Keyframe {
+    /// Seconds since the start of the effect
+    pub time: f32,
+    pub value: f32,
+    /// How to get from this keyframe to the next one
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+/// A value that changes over time, made of [`Keyframe`]s. Before the first keyframe or after
+/// the last one, the value is held constant.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(from = "Vec<Keyframe>")]
+pub struct Curve {
+    /// Always sorted by time
+    keyframes: Vec<Keyframe>,
+}
+
+impl From<Vec<Keyframe>> for Curve {
+    fn from(mut keyframes: Vec<Keyframe>) -> Self {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { keyframes }
+    }
+}
+
+impl Curve {
+    /// The value of the curve at `time`, or `None` if it has no keyframes at all.
+    pub fn sample(&self, time: f32) -> Option<f32> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+
+        if time <= first.time {
+            return Some(first.value);
+        }
+        if time >= last.time {
+            return Some(last.value);
+        }
+
+        // the first keyframe after `time`. This can't be the first or past the end,
+        // thanks to the checks above
+        let next = self.keyframes.partition_point(|keyframe| keyframe.time <= time);
+        let (from, to) = (self.keyframes[next - 1], self.keyframes[next]);
+
+        let t = (time - from.time) / (to.time - from.time);
+        Some(from.value + (to.value - from.value) * from.easing.ease(t))
+    }
+}
+
+/// Every material uniform that can be animated with a [`Curve`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum AnimatedUniform {
+    NoiseMagnitude,
+    NoiseScale,
+    TimeScale,
+    /// How far the mesh has dissolved or burst into bubbles
+    Progress,
+    DissolveEdgeWidth,
+    BubbleRadius,
+}
+
+#[derive(Resource, Debug, Clone, Default)]
+pub struct ParameterCurves(pub HashMap<AnimatedUniform, Curve>);
+
+/// The current value of every animated uniform, for the material systems to copy from.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct AnimatedUniforms {
+    pub noise_magnitude: f32,
+    pub noise_scale: f32,
+    pub time_scale: f32,
+    pub progress: f32,
+    pub dissolve_edge_width: f32,
+    pub bubble_radius: f32,
+}
+
+fn evaluate_curves(
+    time: Res<Time>,
+    settings: Res<EffectSettings>,
+    curves: Res<ParameterCurves>,
+    mut uniforms: ResMut<AnimatedUniforms>,
+) {
+    let elapsed = time.elapsed_seconds();
+    let effect_time = settings.effect_time(elapsed);
+
+    let sample = |uniform, default| {
+        curves
+            .0
+            .get(&uniform)
+            .and_then(|curve| curve.sample(effect_time))
+            .unwrap_or(default)
+    };
+
+    *uniforms = AnimatedUniforms {
+        noise_magnitude: sample(AnimatedUniform::NoiseMagnitude, settings.noise_magnitude),
+        noise_scale: sample(AnimatedUniform::NoiseScale, settings.noise_scale),
+        time_scale: sample(AnimatedUniform::TimeScale, settings.time_scale),
+        progress: sample(AnimatedUniform::Progress, settings.progress_at(elapsed)),
+        dissolve_edge_width: sample(
+            AnimatedUniform::DissolveEdgeWidth,
+            settings.dissolve_edge_width,
+        ),
+        bubble_radius: sample(AnimatedUniform::BubbleRadius, settings.bubble_radius),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, value: f32, easing: Easing) -> Keyframe {
+        Keyframe {
+            time,
+            value,
+            easing,
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn easings_at_ends_and_middle() {
+        let cases = [
+            (Easing::Linear, 0.5),
+            (Easing::EaseIn, 0.25),
+            (Easing::EaseOut, 0.75),
+            (Easing::EaseInOut, 0.5),
+            // CSS's `ease`, which is just past halfway at x = 0.5
+            (Easing::Bezier(Vec2::new(0.25, 0.1), Vec2::new(0.25, 1.0)), 0.8024),
+        ];
+
+        for (easing, middle) in cases {
+            assert_close(easing.ease(0.0), 0.0);
+            assert_close(easing.ease(0.5), middle);
+            assert_close(easing.ease(1.0), 1.0);
+        }
+
+        assert_eq!(Easing::Step.ease(0.0), 0.0);
+        assert_eq!(Easing::Step.ease(0.5), 0.0);
+        assert_eq!(Easing::Step.ease(1.0), 0.0);
+    }
+
+    #[test]
+    fn easing_clamps_t() {
+        assert_eq!(Easing::Linear.ease(-1.0), 0.0);
+        assert_eq!(Easing::Linear.ease(2.0), 1.0);
+    }
+
+    #[test]
+    fn linear_bezier_is_linear() {
+        let easing = Easing::Bezier(
+            Vec2::new(1.0 / 3.0, 1.0 / 3.0),
+            Vec2::new(2.0 / 3.0, 2.0 / 3.0),
+        );
+        for i in 0..=10 {
+            let t = i as f32 / 10.0;
+            assert_close(easing.ease(t), t);
+        }
+    }
+
+    #[test]
+    fn bezier_is_monotonic() {
+        let curves = [
+            // ease, ease-in, ease-out, ease-in-out
+            (Vec2::new(0.25, 0.1), Vec2::new(0.25, 1.0)),
+            (Vec2::new(0.42, 0.0), Vec2::new(1.0, 1.0)),
+            (Vec2::new(0.0, 0.0), Vec2::new(0.58, 1.0)),
+            (Vec2::new(0.42, 0.0), Vec2::new(0.58, 1.0)),
+            // flat spots, where Newton's method gives up
+            (Vec2::new(0.0, 1.0), Vec2::new(1.0, 0.0)),
+            (Vec2::new(1.0, 0.0), Vec2::new(0.0, 1.0)),
+        ];
+
+        for (p1, p2) in curves {
+            let mut previous = cubic_bezier(p1, p2, 0.0);
+            for i in 1..=100 {
+                let value = cubic_bezier(p1, p2, i as f32 / 100.0);
+                assert!(
+                    value >= previous - 1e-4,
+                    "{p1:?} {p2:?} went from {previous} down to {value} at step {i}"
+                );
+                previous = value;
+            }
+        }
+    }
+
+    #[test]
+    fn empty_curve_has_no_value() {
+        assert_eq!(Curve::default().sample(0.0), None);
+        assert_eq!(Curve::from(Vec::new()).sample(1.0), None);
+    }
+
+    #[test]
+    fn single_keyframe_is_constant() {
+        let curve = Curve::from(vec![keyframe(1.0, 5.0, Easing::EaseIn)]);
+        assert_eq!(curve.sample(0.0), Some(5.0));
+        assert_eq!(curve.sample(1.0), Some(5.0));
+        assert_eq!(curve.sample(2.0), Some(5.0));
+    }
+
+    #[test]
+    fn clamps_outside_keyframes() {
+        let curve = Curve::from(vec![
+            keyframe(1.0, 2.0, Easing::Linear),
+            keyframe(3.0, 6.0, Easing::Linear),
+        ]);
+        assert_eq!(curve.sample(-10.0), Some(2.0));
+        assert_eq!(curve.sample(1.0), Some(2.0));
+        assert_eq!(curve.sample(3.0), Some(6.0));
+        assert_eq!(curve.sample(10.0), Some(6.0));
+    }
+
+    #[test]
+    fn interpolates_between_keyframes() {
+        let curve = Curve::from(vec![
+            keyframe(0.0, 0.0, Easing::Linear),
+            keyframe(2.0, 4.0, Easing::EaseIn),
+            keyframe(4.0, 8.0, Easing::Linear),
+        ]);
+        assert_close(curve.sample(1.0).unwrap(), 2.0);
+        // each segment uses the easing of the keyframe it starts at
+        assert_close(curve.sample(3.0).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn keyframes_are_sorted() {
+        let curve = Curve::from(vec![
+            keyframe(2.0, 4.0, Easing::Linear),
+            keyframe(0.0, 0.0, Easing::Linear),
+        ]);
+        assert_close(curve.sample(1.0).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn step_holds_until_next_keyframe() {
+        let curve = Curve::from(vec![
+            keyframe(0.0, 1.0, Easing::Step),
+            keyframe(1.0, 2.0, Easing::Step),
+            keyframe(2.0, 3.0, Easing::Linear),
+        ]);
+        assert_eq!(curve.sample(0.0), Some(1.0));
+        assert_eq!(curve.sample(0.999), Some(1.0));
+        // exactly on a keyframe, its own value takes over
+        assert_eq!(curve.sample(1.0), Some(2.0));
+        assert_eq!(curve.sample(1.5), Some(2.0));
+        assert_eq!(curve.sample(2.0), Some(3.0));
+    }
+}
@@ -0,0 +1,100 @@
+//! Diagnostics for the cost of the effect, reported alongside the frame time by
+//! [`LogDiagnosticsPlugin`](bevy::diagnostic::LogDiagnosticsPlugin).
+//!
+//! The bubble counts come from the render world, so they lag a frame behind.
+
+use std::sync::atomic::AtomicU64;
+
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics};
+use bevy::prelude::*;
+
+use crate::bubbles::BubblesStats;
+use crate::mesh_storage::MeshStorageStats;
+use crate::settings::{EffectPhase, EffectSettings};
+
+pub struct EffectDiagnosticsPlugin;
+
+impl Plugin for EffectDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup_diagnostics)
+            .add_system(record_diagnostics);
+    }
+}
+
+pub const BUBBLES_DRAWN: DiagnosticId =
+    DiagnosticId::from_u128(0x6c1f4b0e_9a57_4f2e_b3d1_2f8a0c7e5d91);
+pub const TRIANGLES_CONSIDERED: DiagnosticId =
+    DiagnosticId::from_u128(0x0d8e3a6b_71c4_4c8a_9e25_b46f1d2a7c03);
+pub const STORAGE_BYTES_UPLOADED: DiagnosticId =
+    DiagnosticId::from_u128(0xa4e7c219_5b3d_48f6_8c0e_e91b6d4f2a58);
+pub const BIND_GROUPS_PREPARED: DiagnosticId =
+    DiagnosticId::from_u128(0x3f9b5d82_c6a1_4e07_a4d8_7c2e0b9f1e64);
+pub const POSE_TIME: DiagnosticId =
+    DiagnosticId::from_u128(0x47b1e9d3_8a2c_4f60_b5e7_1c9d0a3f6e28);
+pub const PREPARE_TIME: DiagnosticId =
+    DiagnosticId::from_u128(0xe2c80f47_1d9a_4b35_96f3_58a7d3c1b20e);
+pub const QUEUE_TIME: DiagnosticId =
+    DiagnosticId::from_u128(0x81d6a3f0_4e2b_4d19_b7c5_0f3e9a6d8c42);
+pub const MESH_CACHE_BYTES: DiagnosticId =
+    DiagnosticId::from_u128(0x9c4d1e7a_2b60_4f8d_a3e9_6d5b0c8f7a21);
+pub const EFFECT_PHASE: DiagnosticId =
+    DiagnosticId::from_u128(0x5a0e7b3c_f814_4a62_8d9b_c3f1e2074b86);
+pub const EFFECT_PROGRESS: DiagnosticId =
+    DiagnosticId::from_u128(0xb79f2c15_6e0d_4387_a1c4_94d8f5e3a017);
+
+const MAX_HISTORY: usize = 20;
+
+fn setup_diagnostics(mut diagnostics: ResMut<Diagnostics>) {
+    let diagnostic = |id, name: &'static str, suffix: &'static str| {
+        Diagnostic::new(id, name, MAX_HISTORY).with_suffix(suffix)
+    };
+
+    diagnostics.add(diagnostic(BUBBLES_DRAWN, "bubbles_drawn", ""));
+    diagnostics.add(diagnostic(TRIANGLES_CONSIDERED, "bubble_triangles_considered", ""));
+    diagnostics.add(diagnostic(STORAGE_BYTES_UPLOADED, "bubble_storage_uploaded", "B"));
+    diagnostics.add(diagnostic(BIND_GROUPS_PREPARED, "bubble_bind_groups_prepared", ""));
+    diagnostics.add(diagnostic(POSE_TIME, "pose_meshes", "ms"));
+    diagnostics.add(diagnostic(PREPARE_TIME, "prepare_mesh_storage", "ms"));
+    diagnostics.add(diagnostic(QUEUE_TIME, "queue_draw_bubbles", "ms"));
+    diagnostics.add(diagnostic(MESH_CACHE_BYTES, "bubble_mesh_cache", "B"));
+    // 0 = wobble, 1 = burst, 2 = bubbles, 3 = reform
+    diagnostics.add(diagnostic(EFFECT_PHASE, "effect_phase", ""));
+    diagnostics.add(diagnostic(EFFECT_PROGRESS, "effect_progress", ""));
+}
+
+fn record_diagnostics(
+    mut diagnostics: ResMut<Diagnostics>,
+    time: Res<Time>,
+    settings: Res<EffectSettings>,
+    stats: Option<Res<BubblesStats>>,
+    storage_stats: Option<Res<MeshStorageStats>>,
+) {
+    let elapsed = time.elapsed_seconds();
+    let phase = match settings.phase_at(elapsed).0 {
+        EffectPhase::Wobble => 0.0,
+        EffectPhase::Burst => 1.0,
+        EffectPhase::Bubbles => 2.0,
+        EffectPhase::Reform => 3.0,
+    };
+    diagnostics.add_measurement(EFFECT_PHASE, || phase);
+    diagnostics.add_measurement(EFFECT_PROGRESS, || settings.progress_at(elapsed) as f64);
+
+    let mut record = |id, value: f64| diagnostics.add_measurement(id, || value);
+
+    if let Some(stats) = stats {
+        let counters = stats.counters();
+        record(BUBBLES_DRAWN, BubblesStats::take(&counters.bubbles_drawn) as f64);
+        record(QUEUE_TIME, BubblesStats::take(&counters.queue_micros) as f64 / 1000.0);
+    }
+
+    if let Some(stats) = storage_stats {
+        let counters = stats.counters();
+        let take = |counter: &AtomicU64| MeshStorageStats::take(counter) as f64;
+        record(TRIANGLES_CONSIDERED, take(&counters.triangles_considered));
+        record(STORAGE_BYTES_UPLOADED, take(&counters.storage_bytes_uploaded));
+        record(BIND_GROUPS_PREPARED, take(&counters.bind_groups_prepared));
+        record(POSE_TIME, take(&counters.pose_micros) / 1000.0);
+        record(PREPARE_TIME, take(&counters.prepare_micros) / 1000.0);
+        record(MESH_CACHE_BYTES, MeshStorageStats::get(&counters.mesh_cache_bytes) as f64);
+    }
+}
@@ -0,0 +1,192 @@
+//! Drop a `.gltf` or `.glb` file onto the window to replace the model with it. The new model
+//! gets the same custom materials as the original one, and the camera is moved to frame it.
+//! The old model stays until the new one has loaded, so there's something to look at in the
+//! meantime, and if it fails to load.
+
+use bevy::asset::{AssetPath, LoadState};
+use bevy::log;
+use bevy::prelude::*;
+use bevy::render::primitives::Aabb;
+use bevy::render::view::NoFrustumCulling;
+use bevy::scene::SceneInstance;
+
+use crate::camera::OrbitCamera;
+use crate::preset::TeleportPreset;
+use crate::status::StatusMessage;
+use crate::{Colette, UseCustomMaterial};
+
+pub struct DragAndDropPlugin;
+
+impl Plugin for DragAndDropPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(load_dropped_models)
+            .add_system(report_load_failures)
+            .add_system(replace_old_models)
+            .add_system(frame_loaded_models);
+    }
+}
+
+/// Marks a dropped model which hasn't finished loading yet, so the camera can frame it
+/// once it has.
+#[derive(Component, Debug)]
+struct LoadingModel {
+    path: String,
+}
+
+/// Marks a dropped model whose scene hasn't loaded yet, so the model it replaces is still
+/// shown. Removed by [`replace_old_models`] once it has.
+#[derive(Component, Debug)]
+struct ReplacingModel;
+
+fn load_dropped_models(
+    mut commands: Commands,
+    mut events: EventReader<FileDragAndDrop>,
+    asset_server: Res<AssetServer>,
+    replacing: Query<Entity, With<ReplacingModel>>,
+    presets: Query<&Handle<TeleportPreset>, With<Colette>>,
+    mut status: EventWriter<StatusMessage>,
+) {
+    for event in events.iter() {
+        let FileDragAndDrop::DroppedFile { path_buf, .. } = event else { continue };
+
+        let is_gltf = matches!(
+            path_buf.extension().and_then(|ext| ext.to_str()),
+            Some("gltf" | "glb")
+        );
+        if !is_gltf {
+            let message = format!(
+                "can't load {}: only .gltf and .glb files are supported",
+                path_buf.display()
+            );
+            status.send(StatusMessage::error(message));
+            continue;
+        }
+
+        // keep whatever preset the old model was using
+        let preset = presets.iter().next().cloned();
+
+        // anything dropped before this which is still loading is out of date already
+        for model in &replacing {
+            commands.entity(model).despawn_recursive();
+        }
+
+        let path = path_buf.display().to_string();
+        log::info!("loading dropped model {path}");
+        status.send(StatusMessage::info(format!("loading {path}...")));
+
+        // absolute paths replace the asset folder, so this works for files anywhere
+        let mut model = commands.spawn((
+            SceneBundle {
+                scene: asset_server.load(AssetPath::new(
+                    path_buf.clone(),
+                    Some("Scene0".to_string()),
+                )),
+                ..default()
+            },
+            Colette,
+            UseCustomMaterial,
+            NoFrustumCulling,
+            LoadingModel { path },
+            ReplacingModel,
+        ));
+        if let Some(preset) = preset {
+            model.insert(preset);
+        }
+    }
+}
+
+fn report_load_failures(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    scenes: Res<Assets<Scene>>,
+    models: Query<(Entity, &Handle<Scene>, &LoadingModel), With<ReplacingModel>>,
+    mut status: EventWriter<StatusMessage>,
+) {
+    for (entity, handle, loading) in &models {
+        let error = match asset_server.get_load_state(handle) {
+            LoadState::Failed => "the file couldn't be read or parsed, see the log for details",
+            // the file loaded, but didn't have a scene in it
+            LoadState::Loaded if scenes.get(handle).is_none() => "it doesn't contain any scenes",
+            _ => continue,
+        };
+
+        log::error!("failed to load {}: {error}", loading.path);
+        status.send(StatusMessage::error(format!(
+            "failed to load {}: {error}",
+            loading.path
+        )));
+        // which leaves the old model in place
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Once a dropped model's scene has loaded, swap it in for the old model.
+fn replace_old_models(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    scenes: Res<Assets<Scene>>,
+    replacing: Query<(Entity, &Handle<Scene>), With<ReplacingModel>>,
+    old_models: Query<Entity, (With<Colette>, Without<ReplacingModel>)>,
+) {
+    for (entity, handle) in &replacing {
+        // failures are handled by report_load_failures
+        let loaded = asset_server.get_load_state(handle) == LoadState::Loaded;
+        if !loaded || scenes.get(handle).is_none() {
+            continue;
+        }
+
+        for old_model in &old_models {
+            commands.entity(old_model).despawn_recursive();
+        }
+        commands.entity(entity).remove::<ReplacingModel>();
+    }
+}
+
+/// Once a dropped model has spawned (and had its bounds calculated), point the camera at it.
+fn frame_loaded_models(
+    mut commands: Commands,
+    scene_manager: Res<SceneSpawner>,
+    models: Query<(Entity, &SceneInstance, &LoadingModel)>,
+    bounds: Query<(&Aabb, &GlobalTransform)>,
+    mut cameras: Query<&mut OrbitCamera>,
+    mut status: EventWriter<StatusMessage>,
+) {
+    for (entity, instance, loading) in &models {
+        if !scene_manager.instance_is_ready(**instance) {
+            continue;
+        }
+
+        let (min, max) = scene_manager
+            .iter_instance_entities(**instance)
+            .filter_map(|entity| bounds.get(entity).ok())
+            .flat_map(|(aabb, transform)| {
+                // every corner, since the model may be rotated
+                let (center, half) = (Vec3::from(aabb.center), Vec3::from(aabb.half_extents));
+                [-1.0, 1.0].into_iter().flat_map(move |x| {
+                    [-1.0, 1.0].into_iter().flat_map(move |y| {
+                        [-1.0, 1.0].into_iter().map(move |z| {
+                            transform.transform_point(center + half * Vec3::new(x, y, z))
+                        })
+                    })
+                })
+            })
+            .fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), point| (min.min(point), max.max(point)),
+            );
+
+        // bounds are only calculated the frame after the meshes spawn
+        if min.cmpgt(max).any() {
+            continue;
+        }
+
+        let center = (min + max) / 2.0;
+        let size = (max - min).length();
+        for mut camera in &mut cameras {
+            camera.frame(center, size);
+        }
+
+        status.send(StatusMessage::info(format!("loaded {}", loading.path)));
+        commands.entity(entity).remove::<LoadingModel>();
+    }
+}
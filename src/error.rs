@@ -0,0 +1,96 @@
+//! Things that can stop a mesh from getting the custom materials. These are sent as
+//! [`TeleportErrorEvent`]s, once per entity, instead of just being logged, and the entity is
+//! skipped (keeping its original material) so the rest of the scene still works.
+
+use std::fmt;
+
+use bevy::log;
+use bevy::prelude::*;
+
+use crate::mesh_storage::{MeshStorageError, MeshStorageErrorEvent};
+
+pub struct TeleportErrorPlugin;
+
+impl Plugin for TeleportErrorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TeleportErrorEvent>()
+            // in case nothing uses mesh storage
+            .add_event::<MeshStorageErrorEvent>()
+            .add_system(forward_mesh_storage_errors)
+            .add_system(log_errors.after(forward_mesh_storage_errors))
+            .add_system(retry_modified_meshes);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TeleportError {
+    /// The mesh can't be bound as storage buffers, which the bubbles are made from
+    MeshStorage(MeshStorageError),
+    /// There's no custom material made from the entity's `StandardMaterial`
+    MissingMaterial,
+}
+
+impl fmt::Display for TeleportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TeleportError::MeshStorage(error) => error.fmt(f),
+            TeleportError::MissingMaterial => {
+                write!(f, "no custom material was created for the entity's material")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TeleportError {}
+
+impl From<MeshStorageError> for TeleportError {
+    fn from(error: MeshStorageError) -> Self {
+        TeleportError::MeshStorage(error)
+    }
+}
+
+/// Sent when `entity` can't get the custom materials.
+#[derive(Debug, Clone)]
+pub struct TeleportErrorEvent {
+    pub entity: Entity,
+    pub error: TeleportError,
+}
+
+/// Marks an entity that has already failed, so it isn't retried (and reported) every frame.
+/// It's removed again if the entity's mesh is modified, since that may have fixed it.
+#[derive(Component, Debug)]
+pub struct Unsupported;
+
+/// Give entities another go once their mesh changes, e.g. when it's hot-reloaded.
+fn retry_modified_meshes(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<Mesh>>,
+    unsupported: Query<(Entity, &Handle<Mesh>), With<Unsupported>>,
+) {
+    for event in events.iter() {
+        let AssetEvent::Modified { handle } = event else { continue };
+
+        for (entity, mesh) in &unsupported {
+            if mesh == handle {
+                log::debug!("retrying {entity:?}, its mesh was modified");
+                commands.entity(entity).remove::<Unsupported>();
+            }
+        }
+    }
+}
+
+fn forward_mesh_storage_errors(
+    mut storage_errors: EventReader<MeshStorageErrorEvent>,
+    mut events: EventWriter<TeleportErrorEvent>,
+) {
+    events.send_batch(storage_errors.iter().map(|event| TeleportErrorEvent {
+        entity: event.entity,
+        error: event.error.clone().into(),
+    }));
+}
+
+fn log_errors(mut events: EventReader<TeleportErrorEvent>) {
+    for TeleportErrorEvent { entity, error } in events.iter() {
+        log::error!("skipping {entity:?}: {error}");
+    }
+}
@@ -0,0 +1,118 @@
+//! A few lighting setups to judge the effect under. Press `L` to cycle through them.
+
+use bevy::log;
+use bevy::prelude::*;
+
+pub struct LightingPlugin;
+
+impl Plugin for LightingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LightingPreset>()
+            .add_system(cycle_lighting_preset)
+            .add_system(apply_lighting_preset.after(cycle_lighting_preset));
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LightingPreset {
+    /// Warm key light, cool fill light and a rim light from behind
+    #[default]
+    ThreePoint,
+    /// A single directional light with shadows, like the sun
+    KeyLight,
+}
+
+impl LightingPreset {
+    const ALL: [LightingPreset; 2] = [LightingPreset::ThreePoint, LightingPreset::KeyLight];
+
+    fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&preset| preset == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// Marks lights spawned for the current [`LightingPreset`], to remove when switching.
+#[derive(Component)]
+struct PresetLight;
+
+fn cycle_lighting_preset(keys: Res<Input<KeyCode>>, mut preset: ResMut<LightingPreset>) {
+    if keys.just_pressed(KeyCode::L) {
+        *preset = preset.next();
+        log::info!("switched to {:?} lighting", *preset);
+    }
+}
+
+fn apply_lighting_preset(
+    mut commands: Commands,
+    preset: Res<LightingPreset>,
+    mut ambient: ResMut<AmbientLight>,
+    lights: Query<Entity, With<PresetLight>>,
+) {
+    if !preset.is_changed() {
+        return;
+    }
+
+    for light in &lights {
+        commands.entity(light).despawn_recursive();
+    }
+
+    match *preset {
+        LightingPreset::ThreePoint => {
+            *ambient = AmbientLight {
+                color: Color::WHITE,
+                brightness: 0.05,
+            };
+
+            let point_light = |color, intensity, position: Vec3| {
+                (
+                    PointLightBundle {
+                        point_light: PointLight {
+                            color,
+                            intensity,
+                            radius: 0.25,
+                            shadows_enabled: true,
+                            ..default()
+                        },
+                        transform: Transform::from_translation(position),
+                        ..default()
+                    },
+                    PresetLight,
+                )
+            };
+
+            // the model faces the default camera, which is at +x, -z
+            commands.spawn(point_light(
+                Color::rgb(1.0, 0.85, 0.7),
+                3500.0,
+                Vec3::new(3.0, 3.0, -1.0),
+            ));
+            commands.spawn(point_light(
+                Color::rgb(0.6, 0.7, 1.0),
+                1200.0,
+                Vec3::new(-1.0, 1.5, -3.0),
+            ));
+            commands.spawn(point_light(Color::WHITE, 3000.0, Vec3::new(-2.0, 3.0, 2.5)));
+        }
+        LightingPreset::KeyLight => spawn_key_light(&mut commands, &mut ambient),
+    }
+}
+
+fn spawn_key_light(commands: &mut Commands, ambient: &mut AmbientLight) {
+    *ambient = AmbientLight {
+        color: Color::rgb(0.7, 0.8, 1.0),
+        brightness: 0.1,
+    };
+
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                illuminance: 20000.0,
+                shadows_enabled: true,
+                ..default()
+            },
+            transform: Transform::from_xyz(3.0, 5.0, -2.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        },
+        PresetLight,
+    ));
+}
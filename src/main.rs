@@ -1,28 +1,67 @@
 use bevy::asset::HandleId;
+use bevy::core_pipeline::bloom::BloomSettings;
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
+use bevy::ecs::system::EntityCommands;
 use bevy::log;
 use bevy::prelude::*;
-use bevy::render::mesh::VertexAttributeValues;
+use bevy::render::mesh::skinning::SkinnedMesh;
 use bevy::render::renderer::RenderDevice;
 use bevy::render::settings::WgpuFeatures;
 use bevy::render::view::NoFrustumCulling;
 use bevy::scene::SceneInstance;
-use bevy::utils::HashMap;
-use inline_tweak::tweak;
+use bevy::utils::{HashMap, HashSet};
 
 mod bubbles;
+mod camera;
+mod cli;
+mod curves;
+mod diagnostics;
+mod drop;
+mod error;
+mod lighting;
+mod mesh_storage;
+mod morph;
 mod noisy;
+mod preset;
+mod settings;
+mod status;
+mod teleport;
 
 use self::bubbles::{BubblesMaterial, BubblesMaterialPlugin};
+use self::camera::{OrbitCamera, OrbitCameraPlugin};
+use self::cli::{Args, ArgsError, Effect};
+use self::curves::{AnimatedUniforms, ParameterCurvesPlugin};
+use self::diagnostics::EffectDiagnosticsPlugin;
+use self::drop::DragAndDropPlugin;
+use self::error::{TeleportError, TeleportErrorEvent, TeleportErrorPlugin, Unsupported};
+use self::lighting::LightingPlugin;
+use self::mesh_storage::validate_mesh;
+use self::morph::MorphPlugin;
 use self::noisy::NoisyVertsMaterial;
+use self::preset::{TeleportPreset, TeleportPresetPlugin};
+use self::settings::{EffectSettings, EffectSettingsPlugin};
+use self::status::StatusPlugin;
+use self::teleport::{TeleportMaterial, TeleportMaterialPlugin};
 
 fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(ArgsError::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprint!("error: {err}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
     let mut app = App::new();
     app.add_plugins(
         DefaultPlugins
             .set(WindowPlugin {
                 primary_window: Some(Window {
-                    resolution: (800.0, 600.0).into(),
+                    resolution: args.window_size.into(),
                     ..default()
                 }),
                 ..default()
@@ -49,19 +88,41 @@ fn main() {
         return;
     }
 
-    app.insert_resource(Msaa::Sample8)
-        .insert_resource(ClearColor(Color::GRAY))
+    app.insert_resource(args.msaa())
+        .insert_resource(ClearColor(args.background))
         .init_resource::<Materials>()
+        .insert_resource(EffectSettings {
+            auto_rotate: args.rotate,
+            bubble_color_source: args.bubble_color,
+            bubble_shading: args.bubble_shading,
+            // bright enough to bloom, otherwise there's no point to HDR
+            bubble_emissive_intensity: if args.hdr { 4.0 } else { 1.0 },
+            ..default()
+        })
+        .insert_resource(args.lighting)
+        .insert_resource(args)
+        .add_plugin(EffectSettingsPlugin)
+        .add_plugin(OrbitCameraPlugin)
+        .add_plugin(StatusPlugin)
+        .add_plugin(DragAndDropPlugin)
+        .add_plugin(LightingPlugin)
+        .add_plugin(MorphPlugin)
+        .add_plugin(TeleportPresetPlugin)
+        .add_plugin(ParameterCurvesPlugin)
+        .add_plugin(TeleportErrorPlugin)
         .add_plugin(BubblesMaterialPlugin)
         .add_plugin(LogDiagnosticsPlugin::default())
         .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(EffectDiagnosticsPlugin)
         .add_plugin(MaterialPlugin::<NoisyVertsMaterial>::default())
+        .add_plugin(TeleportMaterialPlugin)
         .add_startup_system(setup)
-        .add_system(initialize_materials)
-        .add_system(set_custom_material)
+        .add_system(sync_materials)
+        .add_system(set_custom_material.after(sync_materials))
         .add_system(rotate_model)
         .add_system(animate_noise)
         .add_system(animate_bubbles)
+        .add_system(animate_teleport)
         // GO!
         .run();
 }
@@ -70,44 +131,38 @@ fn main() {
 struct Materials {
     bubbles: HashMap<HandleId, Handle<BubblesMaterial>>,
     noisy: HashMap<HandleId, Handle<NoisyVertsMaterial>>,
+    teleport: HashMap<HandleId, Handle<TeleportMaterial>>,
 }
 
 #[derive(Component)]
 struct Colette;
 
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    commands.spawn(Camera3dBundle {
-        transform: Transform::from_xyz(2.0, 1.5, -2.0)
-            .looking_at(Vec3::new(0.0, 0.75, 0.0), Vec3::Y),
-        ..default()
-    });
-
-    commands.spawn(PointLightBundle {
-        point_light: PointLight {
-            color: Color::rgb(1.0, 0.7, 0.1),
-            intensity: 3500.0,
-            radius: 0.25,
-            ..default()
-        },
-        transform: Transform::from_xyz(-3.5, 3.0, 0.75),
-        ..default()
-    });
-    commands.spawn(PointLightBundle {
-        point_light: PointLight {
-            color: Color::rgb(1.0, 0.5, 0.7),
-            intensity: 6000.0,
-            radius: 0.25,
+/// The font for all the text on screen, since bevy doesn't have a default one
+const UI_FONT: &str = "fonts/DejaVuSansMono.ttf";
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>, args: Res<Args>) {
+    let orbit = OrbitCamera::looking_at(args.camera_position, args.camera_target);
+    let mut camera = commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                hdr: args.hdr,
+                ..default()
+            },
+            transform: orbit.transform(),
             ..default()
         },
-        transform: Transform::from_xyz(-3.5, 3.0, 0.75),
-        ..default()
-    });
+        orbit,
+    ));
+    if args.hdr {
+        camera.insert(BloomSettings::default());
+    }
 
     commands.spawn((
         SceneBundle {
-            scene: asset_server.load("colette/Colette.gltf#Scene0"),
+            scene: asset_server.load(args.scene_path()),
             ..default()
         },
+        asset_server.load::<TeleportPreset, _>("presets/default.teleport.ron"),
         Colette,
         UseCustomMaterial,
         NoFrustumCulling,
@@ -117,48 +172,132 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
 #[derive(Component)]
 struct UseCustomMaterial;
 
-fn initialize_materials(
+/// A material made from a copy of a `StandardMaterial`, plus some settings for the effect.
+trait DerivedMaterial: Material {
+    fn from_standard(standard: StandardMaterial) -> Self;
+
+    /// Replace the copy of the standard material, keeping the effect's own settings.
+    fn set_standard(&mut self, standard: StandardMaterial);
+}
+
+impl DerivedMaterial for BubblesMaterial {
+    fn from_standard(standard: StandardMaterial) -> Self {
+        bubbles::material_from_standard(standard)
+    }
+
+    fn set_standard(&mut self, standard: StandardMaterial) {
+        self.standard = bubbles::material_from_standard(standard).standard;
+    }
+}
+
+impl DerivedMaterial for NoisyVertsMaterial {
+    fn from_standard(standard: StandardMaterial) -> Self {
+        NoisyVertsMaterial {
+            standard: StandardMaterial {
+                alpha_mode: noisy::dissolving_alpha_mode(standard.alpha_mode),
+                ..standard
+            },
+            extended: default(),
+        }
+    }
+
+    fn set_standard(&mut self, standard: StandardMaterial) {
+        self.standard = Self::from_standard(standard).standard;
+    }
+}
+
+impl DerivedMaterial for TeleportMaterial {
+    fn from_standard(standard: StandardMaterial) -> Self {
+        TeleportMaterial {
+            standard: StandardMaterial {
+                alpha_mode: noisy::dissolving_alpha_mode(standard.alpha_mode),
+                ..standard
+            },
+            extended: default(),
+        }
+    }
+
+    fn set_standard(&mut self, standard: StandardMaterial) {
+        self.standard = Self::from_standard(standard).standard;
+    }
+}
+
+/// Keep the custom materials in step with the standard materials they're made from, as those
+/// are loaded, hot-reloaded and removed.
+fn sync_materials(
+    mut events: EventReader<AssetEvent<StandardMaterial>>,
     standard: Res<Assets<StandardMaterial>>,
     mut bubbles: ResMut<Assets<BubblesMaterial>>,
     mut noisy_mats: ResMut<Assets<NoisyVertsMaterial>>,
+    mut teleport_mats: ResMut<Assets<TeleportMaterial>>,
     mut materials: ResMut<Materials>,
 ) {
-    // only need to rerun this whenever new standard materials are added
-    if !standard.is_changed() {
-        return;
+    for event in events.iter() {
+        let (handle, source) = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                (handle, standard.get(handle))
+            }
+            AssetEvent::Removed { handle } => (handle, None),
+        };
+
+        let id = handle.id();
+        log::debug!("syncing custom materials for {id:?}");
+
+        sync_derived(&mut materials.bubbles, &mut bubbles, id, source);
+        sync_derived(&mut materials.noisy, &mut noisy_mats, id, source);
+        sync_derived(&mut materials.teleport, &mut teleport_mats, id, source);
     }
+}
 
-    log::debug!(
-        "got {} materials to copy into custom materials",
-        standard.len()
-    );
-
-    for (id, standard) in standard.iter() {
-        materials.bubbles.entry(id).or_insert_with(|| {
-            log::debug!("creating bubbles mat for for {id:?}");
-
-            bubbles.add(bubbles::material_from_standard(standard.clone()))
-        });
-
-        materials.noisy.entry(id).or_insert_with(|| {
-            log::debug!("creating noisy mat for for {id:?}");
+/// Create, update or drop the material derived from the standard material `id`, depending on
+/// whether the standard material (`source`) exists and whether we've seen it before.
+fn sync_derived<M: DerivedMaterial>(
+    derived: &mut HashMap<HandleId, Handle<M>>,
+    assets: &mut Assets<M>,
+    id: HandleId,
+    source: Option<&StandardMaterial>,
+) {
+    let Some(source) = source
+    else {
+        // entities already using the derived material keep it alive until they're done
+        derived.remove(&id);
+        return;
+    };
 
-            noisy_mats.add(NoisyVertsMaterial {
-                standard: standard.clone(),
-                extended: default(),
-            })
-        });
+    match derived.get(&id).and_then(|handle| assets.get_mut(handle)) {
+        Some(material) => material.set_standard(source.clone()),
+        None => {
+            derived.insert(id, assets.add(M::from_standard(source.clone())));
+        }
     }
 }
 
+/// How long to wait for the derived material before reporting it missing, in seconds. It's
+/// normally created within a frame or two of the standard material loading.
+const MISSING_MATERIAL_TIMEOUT: f32 = 5.0;
+
+#[allow(clippy::too_many_arguments)]
 fn set_custom_material(
     mut commands: Commands,
     scenes: Query<(Entity, &SceneInstance), With<Colette>>,
-    ent_materials: Query<(Entity, &Handle<StandardMaterial>, &Handle<Mesh>)>,
+    ent_materials: Query<
+        (Entity, &Handle<StandardMaterial>, &Handle<Mesh>, Option<&SkinnedMesh>),
+        Without<Unsupported>,
+    >,
     meshes: Res<Assets<Mesh>>,
     scene_manager: Res<SceneSpawner>,
     materials: Res<Materials>,
+    args: Res<Args>,
+    time: Res<Time>,
+    mut errors: EventWriter<TeleportErrorEvent>,
+    // when each entity started waiting for its derived material, and which were reported
+    mut waiting: Local<HashMap<Entity, f32>>,
+    mut reported: Local<HashSet<Entity>>,
 ) {
+    // forget entities which got their material or were despawned
+    waiting.retain(|entity, _| ent_materials.contains(*entity));
+    reported.retain(|entity| ent_materials.contains(*entity));
+
     for (entity, instance) in &scenes {
         if !scene_manager.instance_is_ready(**instance) {
             log::debug!("scene instance {entity:?} not spawned yet");
@@ -167,59 +306,87 @@ fn set_custom_material(
 
         // Based on https://github.com/bevyengine/bevy/discussions/8533
         for scene_ent in scene_manager.iter_instance_entities(**instance) {
-            let Ok((ent, standard_mat, mesh_handle)) = ent_materials.get(scene_ent) else { continue };
-
-            let Some(mesh) = meshes.get(mesh_handle)
+            let Ok((ent, standard_mat, mesh_handle, skinned)) = ent_materials.get(scene_ent)
             else {
-                log::error!("mesh not found {mesh_handle:?}");
                 continue;
             };
 
-            let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION)
-            else {
-                log::error!("no mesh positions found");
-                return;
+            // still loading
+            let Some(mesh) = meshes.get(mesh_handle) else { continue };
+
+            // only the bubbles are drawn from mesh storage, the noisy mesh is drawn as it is
+            let valid = match args.effect {
+                Effect::Teleport | Effect::Bubbles => validate_mesh(mesh, skinned.is_some()),
+                Effect::Noisy => Ok(()),
+            };
+            if let Err(error) = valid {
+                errors.send(TeleportErrorEvent {
+                    entity: ent,
+                    error: error.into(),
+                });
+                commands.entity(ent).insert(Unsupported);
+                continue;
+            }
+
+            let id = standard_mat.id();
+            let mut ent_commands = commands.entity(ent);
+            let material_found = match args.effect {
+                Effect::Teleport => insert_material(&mut ent_commands, &materials.teleport, id),
+                Effect::Noisy => insert_material(&mut ent_commands, &materials.noisy, id),
+                Effect::Bubbles => insert_material(&mut ent_commands, &materials.bubbles, id),
             };
 
-            let colors: String = positions[..positions.len().min(36)]
-                .iter()
-                .skip(1)
-                .step_by(3)
-                .map(|&[r, g, b]| {
-                    let (r, g, b) = (r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0));
-                    format!(
-                        "  rgb({}, {}, {}) {:?}\n",
-                        (255.0 * r) as u8,
-                        (255.0 * g) as u8,
-                        (255.0 * b) as u8,
-                        [r, g, b]
-                    )
-                })
-                .collect();
-
-            log::debug!("First several positions:\n{colors}");
-
-            let Some(bubble_mat) = materials.bubbles.get(&standard_mat.id()) else { continue };
-            let Some(_noisy_mat) = materials.noisy.get(&standard_mat.id()) else { continue };
-
-            log::debug!("updating {ent:?} material to {bubble_mat:?}");
-
-            commands
-                .entity(ent)
-                .remove::<Handle<StandardMaterial>>()
-                .insert(bubble_mat.clone());
+            // the derived material may just not be created yet, so keep trying, but say
+            // something if it takes too long. Until then the entity keeps its original
+            // material, so it still shows up as normal
+            if !material_found {
+                let now = time.elapsed_seconds();
+                let since = *waiting.entry(ent).or_insert(now);
+                if now - since > MISSING_MATERIAL_TIMEOUT && reported.insert(ent) {
+                    errors.send(TeleportErrorEvent {
+                        entity: ent,
+                        error: TeleportError::MissingMaterial,
+                    });
+                }
+                continue;
+            }
+
+            log::debug!("updated {ent:?} material for {:?}", args.effect);
+            ent_commands.remove::<Handle<StandardMaterial>>();
         }
     }
 }
 
-fn rotate_model(time: Res<Time>, mut query: Query<&mut Transform, With<Colette>>) {
+/// Insert the material derived from the standard material `id`, if there is one.
+fn insert_material<M: Material>(
+    ent_commands: &mut EntityCommands,
+    materials: &HashMap<HandleId, Handle<M>>,
+    id: HandleId,
+) -> bool {
+    let Some(material) = materials.get(&id) else { return false };
+    ent_commands.insert(material.clone());
+    true
+}
+
+fn rotate_model(
+    time: Res<Time>,
+    settings: Res<EffectSettings>,
+    mut query: Query<&mut Transform, With<Colette>>,
+) {
+    if !settings.auto_rotate {
+        return;
+    }
+
     for mut model in &mut query {
-        model.rotate_y(tweak!(0.25) * time.delta_seconds());
+        model.rotate_y(settings.rotation_speed * time.delta_seconds());
     }
 }
 
 // First half of the animation: apply material with noisy vertex shader
 fn animate_noise(
+    time: Res<Time>,
+    settings: Res<EffectSettings>,
+    uniforms: Res<AnimatedUniforms>,
     material_handles: Query<&Handle<NoisyVertsMaterial>>,
     mut materials: ResMut<Assets<NoisyVertsMaterial>>,
 ) {
@@ -228,10 +395,13 @@ fn animate_noise(
     for handle in &material_handles {
         let Some(material) = materials.get_mut(handle) else { continue };
 
-        // TODO: bevy_inspector_egui would probably be nice for these
-        material.extended.noise_magnitude = tweak!(0.15);
-        material.extended.noise_scale = tweak!(60.0);
-        material.extended.time_scale = tweak!(4.0);
+        material.extended.noise_magnitude = uniforms.noise_magnitude;
+        material.extended.noise_scale = uniforms.noise_scale;
+        material.extended.time_scale = uniforms.time_scale;
+        material.extended.dissolve = uniforms.progress;
+        material.extended.dissolve_edge_width = uniforms.dissolve_edge_width;
+        material.extended.dissolve_edge_color = settings.dissolve_edge_color;
+        material.extended.time = time.elapsed_seconds_wrapped();
     }
 }
 
@@ -242,14 +412,45 @@ fn animate_noise(
 //        game, but let's try with a shader just to see if it's feasible
 //
 fn animate_bubbles(
+    settings: Res<EffectSettings>,
+    uniforms: Res<AnimatedUniforms>,
     material_handles: Query<&Handle<BubblesMaterial>>,
     mut materials: ResMut<Assets<BubblesMaterial>>,
 ) {
     for handle in &material_handles {
         let Some(material) = materials.get_mut(handle) else { continue };
 
-        // TODO: bevy_inspector_egui would probably be nice for these
-        material.extended.bubble_radius = tweak!(0.03);
+        material.extended.bubble_radius = uniforms.bubble_radius;
+        material.extended.emissive_color = settings.bubble_emissive_color;
+        material.extended.emissive_intensity = settings.bubble_emissive_intensity;
+        material.extended.color_source = settings.bubble_color_source;
+        material.extended.shading = settings.bubble_shading;
+    }
+}
+
+// Both halves at once: crossfade from the noisy mesh into bubbles with a single material,
+// so there's no pop when switching from one to the other
+fn animate_teleport(
+    time: Res<Time>,
+    settings: Res<EffectSettings>,
+    uniforms: Res<AnimatedUniforms>,
+    material_handles: Query<&Handle<TeleportMaterial>>,
+    mut materials: ResMut<Assets<TeleportMaterial>>,
+) {
+    // TODO: add UI button to play animation or something? For now the effect just loops
+    // through its phases so the transition is easy to watch
+
+    for handle in &material_handles {
+        let Some(material) = materials.get_mut(handle) else { continue };
+
+        material.extended.noise_magnitude = uniforms.noise_magnitude;
+        material.extended.noise_scale = uniforms.noise_scale;
+        material.extended.time_scale = uniforms.time_scale;
+        material.extended.bubble_radius = uniforms.bubble_radius;
+        material.extended.progress = uniforms.progress;
+        material.extended.emissive_color = settings.bubble_emissive_color;
+        material.extended.emissive_intensity = settings.bubble_emissive_intensity;
+        material.extended.time = time.elapsed_seconds_wrapped();
     }
 }
 
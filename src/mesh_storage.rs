@@ -0,0 +1,445 @@
+//! Lets a material's shader read the vertices (plus indices and colors) of the mesh it's
+//! drawn on, as storage buffers. `prepare_materials` builds one bind group per material, so
+//! the buffers can't just be part of the material: instead the material declares placeholder
+//! storage bindings, and [`prepare_mesh_storage`] builds a copy of its bind group for each
+//! entity, with those bindings pointing at the entity's mesh. Whatever draws the entity then
+//! binds its [`MeshStorageBindGroup`] in place of the material's own.
+
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use bevy::core::{Pod, Zeroable};
+use bevy::log;
+use bevy::pbr::{prepare_materials, MaterialPipeline, MeshUniform, RenderMaterials};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use bevy::render::render_asset::prepare_assets;
+use bevy::render::render_resource::{
+    AsBindGroup, BindGroup, BindGroupDescriptor, BindGroupEntry, BufferUsages, ShaderType,
+};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::{RenderApp, RenderSet};
+use bevy::utils::{HashMap, HashSet};
+
+use self::cache::{extract_meshes, mark_used_meshes, UsesMeshStorage};
+use self::error::{send_render_errors, RenderErrors};
+use self::skinning::{extract_joints, skinned_vertices, validate_joints};
+
+mod buffer;
+mod cache;
+mod error;
+mod skinning;
+
+pub use self::buffer::{ReusableBuffer, SizedBuffer};
+pub use self::cache::MeshCache;
+pub use self::error::{validate_mesh, MeshStorageError, MeshStorageErrorEvent};
+pub use self::skinning::ExtractedJoints;
+
+/// A material which gets the mesh it's drawn on as storage buffers.
+///
+/// The material's bind group must have a `#[storage(VERTEX_BINDING, read_only)]` binding of
+/// [`Vertex`], plus a storage binding for each of the optional bindings it wants. Whatever
+/// the material puts in them is replaced by the actual mesh data.
+pub trait MeshStorage: Material {
+    /// The binding the mesh's vertices go in, as an `array<Vertex>`.
+    const VERTEX_BINDING: u32;
+
+    /// The binding the mesh's indices go in, as an `array<u32>`. The vertices are already
+    /// de-indexed (see [`PosedMesh`]), so this is always `0..vertex_count`.
+    const INDEX_BINDING: Option<u32> = None;
+
+    /// The binding the mesh's vertex colors go in, as an `array<vec4<f32>>`, de-indexed like
+    /// the vertices. Meshes without `ATTRIBUTE_COLOR` get a single white color, so shaders
+    /// should clamp the index with `arrayLength`.
+    const COLOR_BINDING: Option<u32> = None;
+}
+
+/// Fills in the [`MeshStorage`] bindings of every entity with a `Handle<M>`. This does not
+/// set up `M` itself as a material, that has to be done separately.
+pub struct MeshStoragePlugin<M>(PhantomData<M>);
+
+impl<M> Default for MeshStoragePlugin<M> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<M: MeshStorage> Plugin for MeshStoragePlugin<M> {
+    fn build(&self, app: &mut App) {
+        // errors and stats are shared by every material, and written from the render world
+        if !app.world.contains_resource::<RenderErrors>() {
+            let errors = RenderErrors::default();
+            let stats = MeshStorageStats::default();
+            app.add_event::<MeshStorageErrorEvent>()
+                .insert_resource(errors.clone())
+                .insert_resource(stats.clone())
+                .add_system(send_render_errors);
+            app.sub_app_mut(RenderApp)
+                .insert_resource(errors)
+                .insert_resource(stats);
+        }
+
+        let render_app = app.sub_app_mut(RenderApp);
+
+        // and so is mesh extraction, and posing them
+        if !render_app.world.contains_resource::<MeshCache>() {
+            render_app
+                .init_resource::<MeshCache>()
+                .init_resource::<PosedMeshes>()
+                .add_system_to_schedule(ExtractSchedule, extract_meshes)
+                .add_system_to_schedule(ExtractSchedule, extract_joints)
+                .add_system(pose_meshes.in_set(RenderSet::Prepare));
+        }
+
+        render_app
+            .add_system_to_schedule(
+                ExtractSchedule,
+                mark_used_meshes::<M>.before(extract_meshes),
+            )
+            .add_system(
+                prepare_mesh_storage::<M>
+                    .in_set(RenderSet::Prepare)
+                    .after(pose_meshes)
+                    .after(prepare_materials::<M>)
+                    .after(prepare_assets::<Mesh>),
+            );
+    }
+}
+
+/// The bind group of the entity's `M`, with the [`MeshStorage`] bindings pointing at the
+/// entity's own mesh. Rebuilt every frame by [`prepare_mesh_storage`].
+#[derive(Component)]
+pub struct MeshStorageBindGroup<M> {
+    pub bind_group: BindGroup,
+    marker: PhantomData<M>,
+}
+
+/// Counters for the work done preparing the storage buffers, shared between the main and
+/// render worlds. The render world adds to them as it goes, and [`MeshStorageStats::take`]
+/// resets them, so they hold the totals since the last time they were read.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct MeshStorageStats(Arc<MeshStorageCounters>);
+
+#[derive(Debug, Default)]
+pub struct MeshStorageCounters {
+    pub triangles_considered: AtomicU64,
+    pub storage_bytes_uploaded: AtomicU64,
+    pub bind_groups_prepared: AtomicU64,
+    /// Time spent in [`pose_meshes`]
+    pub pose_micros: AtomicU64,
+    /// Time spent in `prepare_mesh_storage`, over every [`MeshStorage`] material
+    pub prepare_micros: AtomicU64,
+    /// Not reset when read, since it's a running total rather than per frame
+    pub mesh_cache_bytes: AtomicU64,
+}
+
+impl MeshStorageStats {
+    pub fn counters(&self) -> &MeshStorageCounters {
+        &self.0
+    }
+
+    /// Read a counter and reset it to zero.
+    pub fn take(counter: &AtomicU64) -> u64 {
+        counter.swap(0, Ordering::Relaxed)
+    }
+
+    /// Read a counter without resetting it.
+    pub fn get(counter: &AtomicU64) -> u64 {
+        counter.load(Ordering::Relaxed)
+    }
+
+    fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn set(counter: &AtomicU64, value: u64) {
+        counter.store(value, Ordering::Relaxed);
+    }
+}
+
+/// A helper struct to represent the type of elements in the vertex storage buffer, to make
+/// it easier to derive [`AsBindGroup`] for [`MeshStorage`] materials.
+///
+/// The buffer is uploaded straight from a slice of these, so it's padded to match
+/// `array<Vertex>` in the shaders, where each `vec3` is aligned to 16 bytes.
+#[derive(Debug, Clone, Copy, Pod, Zeroable, ShaderType)]
+#[repr(C)]
+pub struct Vertex {
+    pub position: Vec3,
+    _pad0: f32,
+    pub normal: Vec3,
+    _pad1: f32,
+    pub uv: Vec2,
+    _pad2: Vec2,
+}
+
+impl Vertex {
+    pub fn new(position: Vec3, normal: Vec3, uv: Vec2) -> Self {
+        Self {
+            position,
+            normal,
+            uv,
+            ..Zeroable::zeroed()
+        }
+    }
+}
+
+/// The unskinned vertices of `mesh`, or `None` if it's missing any of the attributes in
+/// [`Vertex`]. Built from the attributes one by one, since the mesh's own vertex buffer has
+/// any other attributes (like colors or tangents) interleaved with them.
+pub fn mesh_vertices(mesh: &Mesh) -> Option<Vec<Vertex>> {
+    let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else { return None };
+    let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+        else { return None };
+    let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0)
+        else { return None };
+
+    let vertices = itertools::izip!(positions, normals, uvs)
+        .map(|(&position, &normal, &uv)| {
+            Vertex::new(Vec3::from(position), Vec3::from(normal), Vec2::from(uv))
+        })
+        .collect();
+
+    Some(vertices)
+}
+
+/// One of `values` (a vertex attribute of `mesh`) for each of the mesh's indices, so that
+/// every three in a row are one of its triangles, like the bubble shaders read them. Indices
+/// past the end of `values` get zeroes.
+pub fn deindex<T: Copy + Zeroable>(mesh: &Mesh, values: Vec<T>) -> Vec<T> {
+    let value = |index: usize| values.get(index).copied().unwrap_or_else(T::zeroed);
+    match mesh.indices() {
+        Some(Indices::U16(indices)) => indices.iter().map(|&i| value(i as usize)).collect(),
+        Some(Indices::U32(indices)) => indices.iter().map(|&i| value(i as usize)).collect(),
+        None => values,
+    }
+}
+
+/// The vertices of every [`MeshStorage`] entity's mesh as its shader sees them, and where
+/// its triangles are. Found once a frame by [`pose_meshes`], so that everything doing the
+/// shader's math on the CPU can share them, rather than skinning the mesh again each time.
+#[derive(Resource, Debug, Default)]
+pub struct PosedMeshes(HashMap<Entity, PosedMesh>);
+
+impl PosedMeshes {
+    pub fn get(&self, entity: Entity) -> Option<&PosedMesh> {
+        self.0.get(&entity)
+    }
+}
+
+#[derive(Debug)]
+pub struct PosedMesh {
+    /// Exactly what goes in the vertex storage buffer, so skinned meshes are already posed.
+    /// Indexed meshes are de-indexed, so there's one vertex per index (and per bubble
+    /// instance), and every three in a row are a triangle
+    pub vertices: Vec<Vertex>,
+    /// The world space center of each triangle of `vertices`, in order. If there's a partial
+    /// triangle at the end, its centroid is of whatever vertices it has
+    pub centroids: Vec<Vec3>,
+}
+
+impl PosedMesh {
+    /// How many bubbles are drawn for the mesh, one per vertex up to `max_bubbles`. Instance
+    /// `i` is drawn for `vertices[i]`.
+    pub fn instance_count(&self, max_bubbles: u32) -> u32 {
+        (self.vertices.len() as u32).min(max_bubbles)
+    }
+}
+
+/// Fill in [`PosedMeshes`], for every entity whose mesh is valid.
+pub fn pose_meshes(
+    meshes: Res<MeshCache>,
+    stats: Res<MeshStorageStats>,
+    errors: Res<RenderErrors>,
+    mut posed: ResMut<PosedMeshes>,
+    mut reported: Local<HashSet<Entity>>,
+    query: Query<
+        (Entity, &Handle<Mesh>, &MeshUniform, Option<&ExtractedJoints>),
+        With<UsesMeshStorage>,
+    >,
+) {
+    let start = Instant::now();
+    let counters = stats.counters();
+    posed.0.clear();
+    // forget despawned entities, in case their ids are reused
+    reported.retain(|entity| query.contains(*entity));
+
+    for (entity, mesh_handle, mesh_uniform, joints) in &query {
+        // can take a few frames after the entity appears, so just wait
+        let Some(mesh) = meshes.get(mesh_handle) else { continue };
+
+        let valid = validate_mesh(mesh, joints.is_some())
+            .and_then(|()| joints.map_or(Ok(()), |joints| validate_joints(mesh, joints)));
+        if let Err(error) = valid {
+            // only once, rather than every frame
+            if reported.insert(entity) {
+                errors.push(entity, error);
+            }
+            continue;
+        }
+
+        // the mesh's own vertex buffer can have other attributes in it, and skinned meshes
+        // need posing anyway, so build the vertices ourselves. The attributes and joints
+        // were all checked above
+        let vertices = match joints {
+            Some(joints) => skinned_vertices(mesh, joints, mesh_uniform.transform),
+            None => mesh_vertices(mesh),
+        };
+        let Some(vertices) = vertices else { continue };
+        // after posing, so shared vertices are only skinned once
+        let vertices = deindex(mesh, vertices);
+
+        // bubble_triangle and bubble_centroid_world_position
+        let centroids = vertices
+            .chunks(3)
+            .map(|triangle| {
+                let sum = triangle.iter().map(|vertex| vertex.position).sum::<Vec3>();
+                mesh_uniform.transform.transform_point3(sum / triangle.len() as f32)
+            })
+            .collect();
+
+        MeshStorageStats::add(&counters.triangles_considered, vertices.len() as u64 / 3);
+        posed.0.insert(entity, PosedMesh { vertices, centroids });
+    }
+
+    MeshStorageStats::add(&counters.pose_micros, start.elapsed().as_micros() as u64);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn prepare_mesh_storage<M: MeshStorage>(
+    mut commands: Commands,
+    prepared_materials: Res<RenderMaterials<M>>,
+    material_pipeline: Res<MaterialPipeline<M>>,
+    meshes: Res<MeshCache>,
+    posed: Res<PosedMeshes>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    stats: Res<MeshStorageStats>,
+    errors: Res<RenderErrors>,
+    mut reported: Local<HashSet<Entity>>,
+    mut logged_layout: Local<bool>,
+    // the storage buffers of every entity, by binding, kept between frames
+    mut buffers: Local<HashMap<Entity, HashMap<u32, ReusableBuffer>>>,
+    query: Query<(Entity, &Handle<Mesh>, &Handle<M>)>,
+) {
+    let start = Instant::now();
+    let counters = stats.counters();
+
+    buffers.retain(|entity, _| query.contains(*entity));
+    reported.retain(|entity| query.contains(*entity));
+
+    for (entity, mesh_handle, material_handle) in &query {
+        // both of these can take a few frames after the entity appears, so just wait
+        let Some(prepared_material) = prepared_materials.get(material_handle) else { continue };
+        let Some(mesh) = meshes.get(mesh_handle) else { continue };
+        // and invalid meshes were already reported
+        let Some(posed) = posed.get(entity) else { continue };
+        if posed.vertices.is_empty() {
+            continue;
+        }
+
+        // every storage binding to replace, with its contents
+        let mut storage = vec![(
+            M::VERTEX_BINDING,
+            Cow::Borrowed(bytemuck::cast_slice(&posed.vertices)),
+        )];
+        if let Some(binding) = M::INDEX_BINDING {
+            let indices: Vec<u32> = (0..posed.vertices.len() as u32).collect();
+            storage.push((binding, Cow::Owned(bytemuck::cast_slice(&indices).to_vec())));
+        }
+        if let Some(binding) = M::COLOR_BINDING {
+            let colors = match mesh.attribute(Mesh::ATTRIBUTE_COLOR) {
+                Some(VertexAttributeValues::Float32x4(colors)) => {
+                    Cow::Owned(bytemuck::cast_slice(&deindex(mesh, colors.clone())).to_vec())
+                }
+                _ => Cow::Owned(bytemuck::cast_slice(&[[1.0_f32; 4]]).to_vec()),
+            };
+            storage.push((binding, colors));
+        }
+
+        let max = render_device.limits().max_storage_buffer_binding_size as u64;
+        let required = storage.iter().map(|(_, data)| data.len()).max().unwrap_or(0) as u64;
+        if required > max {
+            if reported.insert(entity) {
+                errors.push(entity, MeshStorageError::DeviceLimits { required, max });
+            }
+            continue;
+        }
+
+        MeshStorageStats::add(
+            &counters.storage_bytes_uploaded,
+            storage.iter().map(|(_, data)| data.len() as u64).sum(),
+        );
+
+        let entity_buffers = buffers.entry(entity).or_default();
+        let written: Vec<_> = storage
+            .iter()
+            .map(|(binding, data)| {
+                let buffer = entity_buffers.entry(*binding).or_insert_with(|| {
+                    ReusableBuffer::new("mesh storage buf", BufferUsages::STORAGE)
+                });
+                (*binding, buffer.write(&render_device, &render_queue, data))
+            })
+            .collect();
+
+        // once per material type
+        if !*logged_layout {
+            *logged_layout = true;
+            let layout_entries = M::bind_group_layout_entries(&render_device);
+            for layout_entry in &layout_entries {
+                if layout_entry.binding == M::VERTEX_BINDING {
+                    log::debug!("vertex storage layout is {layout_entry:#?}");
+                    log::debug!("vertex has size {}", mem::size_of::<Vertex>());
+                }
+            }
+
+            log::debug!("prepared material layout: {layout_entries:#?}");
+        }
+
+        let entries = prepared_material
+            .bindings
+            .iter()
+            .map(|(index, binding)| {
+                let resource = match written.iter().find(|(storage, _)| storage == index) {
+                    Some((_, buffer)) => buffer.binding(),
+                    None => binding.get_binding(),
+                };
+
+                BindGroupEntry {
+                    binding: *index,
+                    resource,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("mesh storage bind group"),
+            layout: &material_pipeline.material_layout,
+            entries: &entries,
+        });
+        commands.entity(entity).insert(MeshStorageBindGroup::<M> {
+            bind_group,
+            marker: PhantomData,
+        });
+        MeshStorageStats::add(&counters.bind_groups_prepared, 1);
+    }
+
+    MeshStorageStats::add(&counters.prepare_micros, start.elapsed().as_micros() as u64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vertex_matches_shader_layout() {
+        // what the shaders index `array<Vertex>` with
+        assert_eq!(Vertex::min_size().get(), mem::size_of::<Vertex>() as u64);
+        assert_eq!(mem::size_of::<Vertex>(), 48);
+    }
+}
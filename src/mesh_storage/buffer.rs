@@ -0,0 +1,79 @@
+//! Buffers which are rewritten every frame, like the posed vertices of skinned meshes. They're
+//! kept around between frames and only reallocated when what's written outgrows them.
+
+use std::num::NonZeroU64;
+
+use bevy::render::render_resource::{
+    BindingResource, Buffer, BufferBinding, BufferDescriptor, BufferUsages,
+};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+
+pub struct ReusableBuffer {
+    label: &'static str,
+    usage: BufferUsages,
+    buffer: Option<Buffer>,
+}
+
+impl ReusableBuffer {
+    pub fn new(label: &'static str, usage: BufferUsages) -> Self {
+        Self {
+            label,
+            usage,
+            buffer: None,
+        }
+    }
+
+    /// Upload `contents` to the start of the buffer, growing it first if needed. Bindings can't
+    /// be empty, so neither can `contents`.
+    pub fn write(
+        &mut self,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        contents: &[u8],
+    ) -> SizedBuffer {
+        let size = contents.len() as u64;
+        let buffer = match &self.buffer {
+            Some(buffer) if buffer.size() >= size => buffer.clone(),
+            _ => {
+                let buffer = render_device.create_buffer(&BufferDescriptor {
+                    label: Some(self.label),
+                    size,
+                    usage: self.usage | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                self.buffer = Some(buffer.clone());
+                buffer
+            }
+        };
+
+        render_queue.write_buffer(&buffer, 0, contents);
+        SizedBuffer { buffer, size }
+    }
+}
+
+/// The start of a buffer, up to `size` bytes. Binding only that much means shaders don't see
+/// whatever was left past the end by a bigger write, even with `arrayLength`.
+#[derive(Debug, Clone)]
+pub struct SizedBuffer {
+    pub buffer: Buffer,
+    pub size: u64,
+}
+
+impl SizedBuffer {
+    pub fn binding(&self) -> BindingResource {
+        BindingResource::Buffer(BufferBinding {
+            buffer: &self.buffer,
+            offset: 0,
+            size: NonZeroU64::new(self.size),
+        })
+    }
+}
+
+impl From<Buffer> for SizedBuffer {
+    fn from(buffer: Buffer) -> Self {
+        Self {
+            size: buffer.size(),
+            buffer,
+        }
+    }
+}
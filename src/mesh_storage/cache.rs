@@ -0,0 +1,127 @@
+//! CPU copies of the meshes used by [`MeshStorage`] materials, kept in the render world so
+//! their vertices can be uploaded as storage buffers. Only meshes used by one of those
+//! materials are kept, and they're dropped again as soon as they're removed or nothing uses
+//! them any more.
+
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_asset::RenderAsset;
+use bevy::render::Extract;
+use bevy::utils::{HashMap, HashSet};
+
+use super::{MeshStorage, MeshStorageStats};
+
+#[derive(Resource, Debug, Default)]
+pub struct MeshCache {
+    meshes: HashMap<Handle<Mesh>, CachedMesh>,
+    /// Meshes used by any [`MeshStorage`] material this frame, filled in by [`mark_used_meshes`]
+    used: HashSet<Handle<Mesh>>,
+    /// Total size of every mesh in the cache
+    bytes: usize,
+}
+
+#[derive(Debug)]
+struct CachedMesh {
+    mesh: Mesh,
+    bytes: usize,
+}
+
+impl MeshCache {
+    pub fn get(&self, handle: &Handle<Mesh>) -> Option<&Mesh> {
+        self.meshes.get(handle).map(|cached| &cached.mesh)
+    }
+
+    fn insert(&mut self, handle: Handle<Mesh>, mesh: Mesh) {
+        let bytes = mesh_bytes(&mesh);
+        self.bytes += bytes;
+        if let Some(old) = self.meshes.insert(handle, CachedMesh { mesh, bytes }) {
+            self.bytes -= old.bytes;
+        }
+    }
+
+    fn remove(&mut self, handle: &Handle<Mesh>) {
+        if let Some(old) = self.meshes.remove(handle) {
+            self.bytes -= old.bytes;
+        }
+    }
+}
+
+/// How much memory the mesh's vertex and index data takes up.
+fn mesh_bytes(mesh: &Mesh) -> usize {
+    let vertex_bytes: usize = mesh
+        .attributes()
+        .map(|(_, values)| values.get_bytes().len())
+        .sum();
+
+    let index_bytes = match mesh.indices() {
+        Some(Indices::U16(indices)) => indices.len() * 2,
+        Some(Indices::U32(indices)) => indices.len() * 4,
+        None => 0,
+    };
+
+    vertex_bytes + index_bytes
+}
+
+/// Marks the render world entities drawn with any [`MeshStorage`] material, so their meshes
+/// are only posed once however many of those materials there are.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct UsesMeshStorage;
+
+/// Note which meshes (and entities) are drawn with `M`, so [`extract_meshes`] knows to keep
+/// them.
+pub fn mark_used_meshes<M: MeshStorage>(
+    mut commands: Commands,
+    mut previous_len: Local<usize>,
+    query: Extract<Query<(Entity, &Handle<Mesh>), With<Handle<M>>>>,
+    mut cache: ResMut<MeshCache>,
+) {
+    let mut values = Vec::with_capacity(*previous_len);
+    for (entity, handle) in &query {
+        cache.used.insert(handle.clone_weak());
+        values.push((entity, UsesMeshStorage));
+    }
+
+    *previous_len = values.len();
+    commands.insert_or_spawn_batch(values);
+}
+
+/// Runs after every [`mark_used_meshes`], to copy over any newly used or changed meshes and
+/// evict the rest.
+pub fn extract_meshes(
+    mut events: Extract<EventReader<AssetEvent<Mesh>>>,
+    assets: Extract<Res<Assets<Mesh>>>,
+    stats: Res<MeshStorageStats>,
+    mut cache: ResMut<MeshCache>,
+) {
+    // changed meshes are dropped here and copied again below, if they're still used
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle }
+            | AssetEvent::Modified { handle }
+            | AssetEvent::Removed { handle } => cache.remove(handle),
+        }
+    }
+
+    let used = std::mem::take(&mut cache.used);
+
+    let unused: Vec<_> = cache
+        .meshes
+        .keys()
+        .filter(|handle| !used.contains(*handle))
+        .cloned()
+        .collect();
+    for handle in &unused {
+        cache.remove(handle);
+    }
+
+    for handle in used {
+        if cache.meshes.contains_key(&handle) {
+            continue;
+        }
+        if let Some(mesh) = assets.get(&handle) {
+            cache.insert(handle, mesh.extract_asset());
+        }
+    }
+
+    MeshStorageStats::set(&stats.counters().mesh_cache_bytes, cache.bytes as u64);
+}
@@ -0,0 +1,98 @@
+//! Things that stop a mesh from being bound as storage buffers. Most are found while preparing
+//! the buffers in the render world, so they're sent back to the main world as
+//! [`MeshStorageErrorEvent`]s, once per entity.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use bevy::render::mesh::{MeshVertexAttribute, PrimitiveTopology};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MeshStorageError {
+    /// The mesh doesn't have one of the attributes in [`Vertex`](super::Vertex), or the
+    /// joints for skinning it
+    MissingAttribute(&'static str),
+    /// The storage buffers are read a triangle at a time, so the mesh has to be a triangle list
+    UnsupportedTopology(PrimitiveTopology),
+    /// The mesh is too big to bind as a storage buffer on this device
+    DeviceLimits { required: u64, max: u64 },
+    /// A vertex is weighted to a joint the skinned mesh doesn't have
+    JointOutOfRange { index: u16, joints: usize },
+}
+
+impl fmt::Display for MeshStorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshStorageError::MissingAttribute(name) => write!(f, "mesh has no {name} attribute"),
+            MeshStorageError::UnsupportedTopology(topology) => {
+                write!(f, "mesh topology is {topology:?}, but only TriangleList is supported")
+            }
+            MeshStorageError::DeviceLimits { required, max } => write!(
+                f,
+                "mesh needs a {required} byte storage buffer, but the device allows at most {max}"
+            ),
+            MeshStorageError::JointOutOfRange { index, joints } => {
+                write!(f, "mesh uses joint {index}, but its skin only has {joints} joints")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MeshStorageError {}
+
+/// Sent when `entity`'s mesh can't be bound as storage buffers. It's skipped until the mesh
+/// changes, so it won't be drawn by anything that reads them.
+#[derive(Debug, Clone)]
+pub struct MeshStorageErrorEvent {
+    pub entity: Entity,
+    pub error: MeshStorageError,
+}
+
+/// Check that `mesh` has everything the storage buffers are built from.
+pub fn validate_mesh(mesh: &Mesh, skinned: bool) -> Result<(), MeshStorageError> {
+    let topology = mesh.primitive_topology();
+    if topology != PrimitiveTopology::TriangleList {
+        return Err(MeshStorageError::UnsupportedTopology(topology));
+    }
+
+    // these match `Vertex`
+    let required = [
+        Mesh::ATTRIBUTE_POSITION,
+        Mesh::ATTRIBUTE_NORMAL,
+        Mesh::ATTRIBUTE_UV_0,
+    ];
+    let skinning: &[MeshVertexAttribute] = if skinned {
+        &[Mesh::ATTRIBUTE_JOINT_INDEX, Mesh::ATTRIBUTE_JOINT_WEIGHT]
+    } else {
+        &[]
+    };
+
+    for attribute in required.iter().chain(skinning) {
+        if mesh.attribute(attribute.id).is_none() {
+            return Err(MeshStorageError::MissingAttribute(attribute.name));
+        }
+    }
+
+    Ok(())
+}
+
+/// Errors found in the render world, waiting to be sent as events in the main world.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct RenderErrors(Arc<Mutex<Vec<MeshStorageErrorEvent>>>);
+
+impl RenderErrors {
+    pub fn push(&self, entity: Entity, error: MeshStorageError) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(MeshStorageErrorEvent { entity, error });
+    }
+}
+
+pub fn send_render_errors(
+    errors: Res<RenderErrors>,
+    mut events: EventWriter<MeshStorageErrorEvent>,
+) {
+    events.send_batch(errors.0.lock().unwrap().drain(..));
+}
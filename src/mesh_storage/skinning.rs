@@ -0,0 +1,148 @@
+//! CPU skinning for meshes bound as storage buffers. Shaders reading vertices straight out of
+//! the storage buffer don't go through bevy's skinning, so bubbles would otherwise always
+//! burst out of the bind pose. Morph targets are already blended into the mesh by
+//! [`MorphPlugin`](crate::morph::MorphPlugin), so they're skinned along with it.
+
+use bevy::prelude::*;
+use bevy::render::mesh::skinning::{SkinnedMesh, SkinnedMeshInverseBindposes};
+use bevy::render::mesh::VertexAttributeValues;
+use bevy::render::Extract;
+
+use super::{MeshStorageError, Vertex};
+
+/// The world-space matrix for each joint of a skinned mesh, already multiplied by its
+/// inverse bind pose. This is the same thing bevy uploads for GPU skinning.
+#[derive(Component, Debug, Clone)]
+pub struct ExtractedJoints(pub Vec<Mat4>);
+
+// mostly copied from bevy_pbr::extract_skinned_meshes
+pub fn extract_joints(
+    mut commands: Commands,
+    mut previous_len: Local<usize>,
+    query: Extract<Query<(Entity, &ComputedVisibility, &SkinnedMesh)>>,
+    inverse_bindposes: Extract<Res<Assets<SkinnedMeshInverseBindposes>>>,
+    joint_query: Extract<Query<&GlobalTransform>>,
+) {
+    let mut values = Vec::with_capacity(*previous_len);
+
+    for (entity, computed_visibility, skin) in &query {
+        if !computed_visibility.is_visible() {
+            continue;
+        }
+
+        let Some(inverse_bindposes) = inverse_bindposes.get(&skin.inverse_bindposes) else { continue };
+
+        let joints = joint_query
+            .iter_many(&skin.joints)
+            .zip(inverse_bindposes.iter())
+            .map(|(joint, bindpose)| joint.affine() * *bindpose)
+            .collect();
+
+        values.push((entity, ExtractedJoints(joints)));
+    }
+
+    *previous_len = values.len();
+    commands.insert_or_spawn_batch(values);
+}
+
+/// Check that every joint `mesh` is weighted to is one of its `joints`. Unweighted joints are
+/// ignored, since some exporters leave junk in those.
+pub fn validate_joints(mesh: &Mesh, joints: &ExtractedJoints) -> Result<(), MeshStorageError> {
+    let Some(VertexAttributeValues::Uint16x4(joint_indices)) = mesh.attribute(Mesh::ATTRIBUTE_JOINT_INDEX)
+        else { return Ok(()) };
+    let Some(VertexAttributeValues::Float32x4(joint_weights)) = mesh.attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT)
+        else { return Ok(()) };
+
+    let used = joint_indices
+        .iter()
+        .flatten()
+        .zip(joint_weights.iter().flatten())
+        .filter(|(_, weight)| **weight != 0.0)
+        .map(|(&index, _)| index);
+    match used.max() {
+        Some(index) if usize::from(index) >= joints.0.len() => {
+            Err(MeshStorageError::JointOutOfRange {
+                index,
+                joints: joints.0.len(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Pose the vertices of `mesh` with its joints, then move them back into the mesh's local
+/// space (using its `model` transform), so they can be drawn exactly like an unskinned mesh.
+///
+/// Returns `None` if the mesh is missing any of the attributes needed for skinning, or uses a
+/// joint it doesn't have (see [`validate_joints`]).
+pub fn skinned_vertices(mesh: &Mesh, joints: &ExtractedJoints, model: Mat4) -> Option<Vec<Vertex>> {
+    let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else { return None };
+    let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+        else { return None };
+    let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0)
+        else { return None };
+    let Some(VertexAttributeValues::Uint16x4(joint_indices)) = mesh.attribute(Mesh::ATTRIBUTE_JOINT_INDEX)
+        else { return None };
+    let Some(VertexAttributeValues::Float32x4(joint_weights)) = mesh.attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT)
+        else { return None };
+
+    validate_joints(mesh, joints).ok()?;
+
+    let world_to_local = model.inverse();
+
+    let vertices = itertools::izip!(positions, normals, uvs, joint_indices, joint_weights)
+        .map(|(&position, &normal, &uv, indices, &weights)| {
+            // a vertex no joint moves would otherwise collapse onto the origin, with a NaN
+            // normal, so leave it where the mesh puts it
+            let transform = if weights.iter().sum::<f32>().abs() <= f32::EPSILON {
+                Mat4::IDENTITY
+            } else {
+                let skin = indices
+                    .iter()
+                    .zip(weights)
+                    .filter(|(_, weight)| *weight != 0.0)
+                    .map(|(&index, weight)| joints.0[usize::from(index)] * weight)
+                    .fold(Mat4::ZERO, |acc, joint| acc + joint);
+                world_to_local * skin
+            };
+            // normals need the inverse transpose, in case the joints are scaled
+            let normal_transform = Mat3::from_mat4(transform).inverse().transpose();
+
+            Vertex::new(
+                transform.transform_point3(Vec3::from(position)),
+                (normal_transform * Vec3::from(normal)).normalize_or_zero(),
+                Vec2::from(uv),
+            )
+        })
+        .collect();
+
+    Some(vertices)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::PrimitiveTopology;
+
+    use super::*;
+
+    #[test]
+    fn unweighted_vertices_stay_put() {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![[1.0, 2.0, 3.0]]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 1.0, 0.0]]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]]);
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_JOINT_INDEX,
+            VertexAttributeValues::Uint16x4(vec![[0; 4]]),
+        );
+        mesh.insert_attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT, vec![[0.0_f32; 4]]);
+
+        let joints = ExtractedJoints(vec![Mat4::from_translation(Vec3::X)]);
+        let model = Mat4::from_translation(Vec3::Y);
+        let vertices = skinned_vertices(&mesh, &joints, model).unwrap();
+
+        assert_eq!(vertices[0].position, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(vertices[0].normal, Vec3::Y);
+    }
+}
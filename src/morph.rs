@@ -0,0 +1,247 @@
+//! Morph targets (blend shapes) for glTF meshes. Bevy's glTF loader doesn't read them, so
+//! they're read from the file here and blended on the CPU: each morphed entity gets its own
+//! copy of its mesh with the targets applied, which is updated whenever its [`MorphWeights`]
+//! change. Everything that draws the mesh then sees the morphed vertices, including the
+//! storage buffers the bubbles are made from, and skinning still happens on top of them.
+//!
+//! Files are read in the background through the [`AssetServer`]'s [`AssetIo`], and meshes
+//! from them are left alone until that's done.
+//!
+//! Animations can't drive the weights yet, so they start at the mesh's default weights and
+//! only change when something sets them. Buffers embedded as data URIs aren't supported.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bevy::asset::AssetIo;
+use bevy::log;
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+use bevy::tasks::futures_lite::future;
+use bevy::tasks::{IoTaskPool, Task};
+use bevy::utils::HashMap;
+
+pub struct MorphPlugin;
+
+impl Plugin for MorphPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(load_morph_targets)
+            .add_system(apply_morph_weights.after(load_morph_targets));
+    }
+}
+
+/// How much of each morph target to blend into the mesh, in the order they're in the file.
+#[derive(Component, Debug, Clone, Default)]
+pub struct MorphWeights(pub Vec<f32>);
+
+/// The targets of a morphed mesh, and the original mesh they're blended onto. The entity's
+/// `Handle<Mesh>` is swapped for a copy of it, to blend into.
+#[derive(Component, Debug)]
+struct MorphTargets {
+    base: Handle<Mesh>,
+    targets: Arc<Vec<MorphTarget>>,
+}
+
+/// Marks meshes which were checked and don't have any morph targets.
+#[derive(Component, Debug)]
+struct NoMorphTargets;
+
+/// How far each vertex moves when the target's weight is 1. Either list can be empty if the
+/// target doesn't change that attribute.
+#[derive(Debug, Default)]
+struct MorphTarget {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+}
+
+/// The morph targets of every primitive in a glTF file, by mesh and primitive index, along
+/// with the mesh's default weights.
+type FileMorphTargets = HashMap<(usize, usize), (Arc<Vec<MorphTarget>>, Vec<f32>)>;
+
+/// A glTF file's morph targets, which are read on the [`IoTaskPool`].
+enum MorphFile {
+    Reading(Task<Result<FileMorphTargets, BoxError>>),
+    /// `None` if the file couldn't be read, so it's only reported once
+    Read(Option<FileMorphTargets>),
+}
+
+impl MorphFile {
+    fn read(asset_server: &AssetServer, path: &Path) -> Self {
+        let asset_server = asset_server.clone();
+        let path = path.to_path_buf();
+        let task = IoTaskPool::get()
+            .spawn(async move { read_morph_targets(asset_server.asset_io(), &path).await });
+        MorphFile::Reading(task)
+    }
+
+    /// The file's targets, or `None` while it's still being read.
+    fn poll(&mut self, path: &Path) -> Option<Option<&FileMorphTargets>> {
+        if let MorphFile::Reading(task) = self {
+            let result = future::block_on(future::poll_once(task))?;
+            *self = MorphFile::Read(match result {
+                Ok(targets) => Some(targets),
+                Err(err) => {
+                    log::warn!("couldn't read morph targets from {path:?}: {err}");
+                    None
+                }
+            });
+        }
+
+        match self {
+            MorphFile::Reading(_) => None,
+            MorphFile::Read(targets) => Some(targets.as_ref()),
+        }
+    }
+}
+
+fn load_morph_targets(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut files: Local<HashMap<PathBuf, MorphFile>>,
+    query: Query<(Entity, &Handle<Mesh>), (Without<MorphTargets>, Without<NoMorphTargets>)>,
+) {
+    for (entity, handle) in &query {
+        // still loading
+        let Some(mesh) = meshes.get(handle) else { continue };
+
+        let primitive = asset_server.get_handle_path(handle).and_then(|asset_path| {
+            let (mesh, primitive) = parse_primitive_label(asset_path.label()?)?;
+            Some((asset_path.path().to_path_buf(), mesh, primitive))
+        });
+        let Some((path, mesh_index, primitive_index)) = primitive else {
+            commands.entity(entity).insert(NoMorphTargets);
+            continue;
+        };
+
+        let file = files
+            .entry(path.clone())
+            .or_insert_with_key(|path| MorphFile::read(&asset_server, path));
+        // still being read, so try again next frame
+        let Some(file) = file.poll(&path) else { continue };
+        let Some((targets, weights)) =
+            file.and_then(|file| file.get(&(mesh_index, primitive_index)))
+        else {
+            commands.entity(entity).insert(NoMorphTargets);
+            continue;
+        };
+
+        let mut weights = weights.clone();
+        weights.resize(targets.len(), 0.0);
+
+        let morphed = mesh.clone();
+        let morphed = meshes.add(morphed);
+        commands.entity(entity).insert((
+            MorphTargets {
+                base: handle.clone(),
+                targets: targets.clone(),
+            },
+            MorphWeights(weights),
+            morphed,
+        ));
+    }
+}
+
+/// The mesh and primitive index from a label like `Mesh0/Primitive1`, which is how bevy's
+/// glTF loader names the meshes it loads.
+fn parse_primitive_label(label: &str) -> Option<(usize, usize)> {
+    let (mesh, primitive) = label.split_once('/')?;
+    let mesh = mesh.strip_prefix("Mesh")?.parse().ok()?;
+    let primitive = primitive.strip_prefix("Primitive")?.parse().ok()?;
+    Some((mesh, primitive))
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Read every morph target in the glTF file at `path`, an asset path like the asset server's.
+async fn read_morph_targets(
+    asset_io: &dyn AssetIo,
+    path: &Path,
+) -> Result<FileMorphTargets, BoxError> {
+    let gltf = gltf::Gltf::from_slice(&asset_io.load_path(path).await?)?;
+
+    let mut buffers = Vec::new();
+    for buffer in gltf.buffers() {
+        buffers.push(match buffer.source() {
+            gltf::buffer::Source::Bin => gltf.blob.clone().ok_or("missing GLB blob")?,
+            gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") => {
+                return Err("buffers in data URIs aren't supported".into());
+            }
+            gltf::buffer::Source::Uri(uri) => {
+                let buffer_path = path.parent().unwrap_or(Path::new("")).join(uri);
+                asset_io.load_path(&buffer_path).await?
+            }
+        });
+    }
+
+    let mut targets = HashMap::default();
+    for mesh in gltf.meshes() {
+        let weights = mesh.weights().unwrap_or_default().to_vec();
+
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let primitive_targets: Vec<_> = reader
+                .read_morph_targets()
+                .map(|(positions, normals, _tangents)| MorphTarget {
+                    positions: positions.into_iter().flatten().map(Vec3::from).collect(),
+                    normals: normals.into_iter().flatten().map(Vec3::from).collect(),
+                })
+                .collect();
+
+            if !primitive_targets.is_empty() {
+                targets.insert(
+                    (mesh.index(), primitive.index()),
+                    (Arc::new(primitive_targets), weights.clone()),
+                );
+            }
+        }
+    }
+
+    Ok(targets)
+}
+
+fn apply_morph_weights(
+    mut meshes: ResMut<Assets<Mesh>>,
+    query: Query<(&MorphTargets, &MorphWeights, &Handle<Mesh>), Changed<MorphWeights>>,
+) {
+    for (targets, weights, morphed) in &query {
+        let Some(base) = meshes.get(&targets.base) else { continue };
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            base.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            continue;
+        };
+        let mut positions: Vec<Vec3> = positions.iter().copied().map(Vec3::from).collect();
+        let mut normals: Option<Vec<Vec3>> = match base.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(normals)) => {
+                Some(normals.iter().copied().map(Vec3::from).collect())
+            }
+            _ => None,
+        };
+
+        for (target, &weight) in targets.targets.iter().zip(&weights.0) {
+            if weight == 0.0 {
+                continue;
+            }
+            for (position, offset) in positions.iter_mut().zip(&target.positions) {
+                *position += *offset * weight;
+            }
+            if let Some(normals) = &mut normals {
+                for (normal, offset) in normals.iter_mut().zip(&target.normals) {
+                    *normal += *offset * weight;
+                }
+            }
+        }
+
+        let Some(mesh) = meshes.get_mut(morphed) else { continue };
+        let positions: Vec<[f32; 3]> = positions.into_iter().map(Into::into).collect();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        if let Some(normals) = normals {
+            let normals: Vec<[f32; 3]> = normals
+                .into_iter()
+                .map(|normal| normal.normalize_or_zero().into())
+                .collect();
+            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        }
+    }
+}
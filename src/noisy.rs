@@ -9,7 +9,7 @@ use bevy::render::render_resource::{
 
 pub type NoisyVertsMaterial = ExtendedMaterial<NoisyVerts>;
 
-#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[derive(AsBindGroup, TypeUuid, Reflect, Debug, Clone)]
 #[uuid = "eee72aef-5111-4307-a571-191b80a73dbe"]
 pub struct NoisyVerts {
     /// How far (at most) offset vertices should be (in ??? units)
@@ -23,6 +23,23 @@ pub struct NoisyVerts {
     /// The speed at which the shader should animate
     #[uniform(102)]
     pub time_scale: f32,
+
+    /// How much of the mesh has dissolved away, from 0 (none of it) to 1 (all of it)
+    #[uniform(103)]
+    pub dissolve: f32,
+
+    /// How far past the dissolve threshold the edge glow extends
+    #[uniform(104)]
+    pub dissolve_edge_width: f32,
+
+    /// The color of the glow along the edge of the dissolve. Values above 1.0 glow brighter
+    #[uniform(105)]
+    pub dissolve_edge_color: Color,
+
+    /// Seconds since startup, wrapped like bevy's `globals.time`. The wobble animates with
+    /// this instead of the globals, since the prepass and shadow passes don't have them.
+    #[uniform(106)]
+    pub time: f32,
 }
 
 impl Default for NoisyVerts {
@@ -31,6 +48,10 @@ impl Default for NoisyVerts {
             noise_magnitude: 1.0,
             noise_scale: 1.0,
             time_scale: 1.0,
+            dissolve: 0.0,
+            dissolve_edge_width: 0.05,
+            dissolve_edge_color: Color::CYAN,
+            time: 0.0,
         }
     }
 }
@@ -40,12 +61,26 @@ impl Material for NoisyVerts {
         "shaders/noisy_verts.wgsl".into()
     }
 
+    fn fragment_shader() -> ShaderRef {
+        "shaders/noisy_verts.wgsl".into()
+    }
+
+    fn prepass_vertex_shader() -> ShaderRef {
+        "shaders/noisy_verts_prepass.wgsl".into()
+    }
+
+    fn prepass_fragment_shader() -> ShaderRef {
+        "shaders/noisy_verts_prepass.wgsl".into()
+    }
+
     fn specialize(
         _pipeline: &MaterialPipeline<Self>,
         descriptor: &mut RenderPipelineDescriptor,
-        _layout: &MeshVertexBufferLayout,
+        layout: &MeshVertexBufferLayout,
         _key: MaterialPipelineKey<Self>,
     ) -> Result<(), SpecializedMeshPipelineError> {
+        specialize_prepass(descriptor, layout)?;
+
         if let Some(label) = &mut descriptor.label {
             *label = format!("noisy_{label}").into();
         }
@@ -55,3 +90,41 @@ impl Material for NoisyVerts {
         Ok(())
     }
 }
+
+/// Dissolving meshes discard what's dissolved, so they're drawn alpha masked. Otherwise the
+/// shadow pass leaves out the prepass fragment shader, and the whole mesh casts a shadow.
+pub fn dissolving_alpha_mode(alpha_mode: AlphaMode) -> AlphaMode {
+    match alpha_mode {
+        AlphaMode::Opaque => AlphaMode::Mask(0.5),
+        alpha_mode => alpha_mode,
+    }
+}
+
+/// The prepass only reads normals for the normal prepass, but the wobble moves vertices along
+/// them in every pass. Must run before the label is changed, which is how the prepass is told
+/// apart from the main pass.
+pub fn specialize_prepass(
+    descriptor: &mut RenderPipelineDescriptor,
+    layout: &MeshVertexBufferLayout,
+) -> Result<(), SpecializedMeshPipelineError> {
+    if descriptor.label.as_deref() != Some("prepass_pipeline") {
+        return Ok(());
+    }
+
+    // the same locations as bevy's prepass, minus the tangents nothing here uses
+    let mut attributes = vec![
+        Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+        Mesh::ATTRIBUTE_NORMAL.at_shader_location(2),
+    ];
+    if layout.contains(Mesh::ATTRIBUTE_UV_0) {
+        attributes.push(Mesh::ATTRIBUTE_UV_0.at_shader_location(1));
+    }
+    if layout.contains(Mesh::ATTRIBUTE_JOINT_INDEX) && layout.contains(Mesh::ATTRIBUTE_JOINT_WEIGHT)
+    {
+        attributes.push(Mesh::ATTRIBUTE_JOINT_INDEX.at_shader_location(4));
+        attributes.push(Mesh::ATTRIBUTE_JOINT_WEIGHT.at_shader_location(5));
+    }
+    descriptor.vertex.buffers = vec![layout.get_layout(&attributes)?];
+
+    Ok(())
+}
@@ -0,0 +1,140 @@
+//! Effect parameters stored as `.teleport.ron` assets, so they can be saved, shared and
+//! edited while the effect is running (thanks to the asset server watching for changes).
+
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::log;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::{BoxedFuture, HashMap};
+use serde::Deserialize;
+
+use crate::curves::{AnimatedUniform, Curve, ParameterCurves};
+use crate::settings::EffectSettings;
+
+pub struct TeleportPresetPlugin;
+
+impl Plugin for TeleportPresetPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<TeleportPreset>()
+            .init_asset_loader::<TeleportPresetLoader>()
+            .add_system(apply_presets);
+    }
+}
+
+/// A full set of effect parameters. Attach a `Handle<TeleportPreset>` to an entity to apply
+/// it to the [`EffectSettings`], including any later changes to the file.
+#[derive(Debug, Clone, Deserialize, TypeUuid)]
+#[uuid = "6d8b4bd2-3f2e-4a4e-8a0c-2f6b1f7cf1a4"]
+pub struct TeleportPreset {
+    pub phases: PhaseDurations,
+    pub noise: NoisePreset,
+    pub dissolve_edge: DissolveEdgePreset,
+    pub bubbles: BubblesPreset,
+
+    /// Keyframes for any uniforms that should change over the course of the effect,
+    /// instead of using the constant values above.
+    #[serde(default)]
+    pub curves: HashMap<AnimatedUniform, Curve>,
+}
+
+/// How long each phase of the effect lasts, in seconds.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PhaseDurations {
+    pub wobble: f32,
+    pub burst: f32,
+    pub bubbles: f32,
+    pub reform: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NoisePreset {
+    pub magnitude: f32,
+    pub scale: f32,
+    pub time_scale: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DissolveEdgePreset {
+    pub width: f32,
+    pub color: Color,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BubblesPreset {
+    pub radius: f32,
+    pub max_count: u32,
+}
+
+impl TeleportPreset {
+    /// Overwrite everything in `settings` and `curves` that this preset covers.
+    pub fn apply(&self, settings: &mut EffectSettings, curves: &mut ParameterCurves) {
+        curves.0 = self.curves.clone();
+
+        settings.wobble_duration = self.phases.wobble;
+        settings.burst_duration = self.phases.burst;
+        settings.bubbles_duration = self.phases.bubbles;
+        settings.reform_duration = self.phases.reform;
+
+        settings.noise_magnitude = self.noise.magnitude;
+        settings.noise_scale = self.noise.scale;
+        settings.time_scale = self.noise.time_scale;
+
+        settings.dissolve_edge_width = self.dissolve_edge.width;
+        settings.dissolve_edge_color = self.dissolve_edge.color;
+
+        settings.bubble_radius = self.bubbles.radius;
+        settings.max_bubbles = self.bubbles.max_count;
+    }
+}
+
+#[derive(Default)]
+pub struct TeleportPresetLoader;
+
+impl AssetLoader for TeleportPresetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let preset: TeleportPreset = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(preset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["teleport.ron"]
+    }
+}
+
+// The settings are global for now, so if several entities use different presets, whichever
+// was loaded or edited most recently wins.
+fn apply_presets(
+    mut events: EventReader<AssetEvent<TeleportPreset>>,
+    presets: Res<Assets<TeleportPreset>>,
+    users: Query<&Handle<TeleportPreset>>,
+    new_users: Query<&Handle<TeleportPreset>, Changed<Handle<TeleportPreset>>>,
+    mut settings: ResMut<EffectSettings>,
+    mut curves: ResMut<ParameterCurves>,
+) {
+    let mut apply = |handle: &Handle<TeleportPreset>| {
+        if let Some(preset) = presets.get(handle) {
+            log::info!("applying teleport preset {handle:?}");
+            preset.apply(&mut settings, &mut curves);
+        }
+    };
+
+    for event in events.iter() {
+        let (AssetEvent::Created { handle } | AssetEvent::Modified { handle }) = event else { continue };
+
+        if users.iter().any(|used| used == handle) {
+            apply(handle);
+        }
+    }
+
+    // the asset might have been loaded before anything started using it
+    for handle in &new_users {
+        apply(handle);
+    }
+}
@@ -0,0 +1,357 @@
+//! Live-editable parameters for the effect, plus a small in-app panel to edit them.
+//!
+//! The panel is driven entirely by reflection, so any new field added to [`EffectSettings`]
+//! shows up automatically. Press `F1` to show/hide it, `Up`/`Down` to pick a field and
+//! `Left`/`Right` to change it (hold `Shift` for finer steps). Colors change all of their
+//! red, green and blue at once, unless `1`, `2` or `3` is held to pick just one of them.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::reflect::{DynamicEnum, DynamicVariant, ReflectMut, Struct, TypeInfo, VariantInfo};
+use bevy::render::extract_resource::{ExtractResource, ExtractResourcePlugin};
+
+use crate::bubbles::{BubbleShading, ColorSource};
+use crate::UI_FONT;
+
+pub struct EffectSettingsPlugin;
+
+impl Plugin for EffectSettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<EffectSettings>()
+            .init_resource::<EffectSettings>()
+            .add_plugin(ExtractResourcePlugin::<EffectSettings>::default())
+            .add_startup_system(spawn_settings_panel)
+            .add_system(edit_settings)
+            .add_system(update_settings_panel.after(edit_settings));
+    }
+}
+
+/// Everything about the effect that's worth tweaking while it runs.
+#[derive(Resource, Reflect, ExtractResource, Debug, Clone)]
+#[reflect(Resource)]
+pub struct EffectSettings {
+    /// How far (at most) noisy vertices are offset
+    pub noise_magnitude: f32,
+
+    /// The scale of the noise, for both displacement and dissolving
+    pub noise_scale: f32,
+
+    /// The speed at which the noise animates
+    pub time_scale: f32,
+
+    /// How wide the glowing edge of the dissolve is
+    pub dissolve_edge_width: f32,
+
+    /// The color of the glowing edge of the dissolve
+    pub dissolve_edge_color: Color,
+
+    /// How big the bubbles are
+    pub bubble_radius: f32,
+
+    /// Tints the bubbles' emissive color
+    pub bubble_emissive_color: Color,
+
+    /// How bright the bubbles are. Above 1.0 they glow, if the camera is HDR (`--hdr`)
+    pub bubble_emissive_intensity: f32,
+
+    /// Where the bubbles effect gets its colors from
+    pub bubble_color_source: ColorSource,
+
+    /// Whether the bubbles glow, or refract the scene behind them like water droplets
+    pub bubble_shading: BubbleShading,
+
+    /// Whether bubbles close to each other merge into blobs
+    pub metaballs: bool,
+
+    /// How close (in half viewport heights) bubbles have to be to start merging, when
+    /// `metaballs` is on
+    pub metaball_blend_radius: f32,
+
+    /// The most bubbles drawn per mesh. This is mostly to keep the framerate reasonable
+    pub max_bubbles: u32,
+
+    /// Whether each mesh's bubbles are drawn back to front, so they blend correctly. They're
+    /// sorted on the CPU every frame
+    pub sort_bubbles: bool,
+
+    /// How long to show just the wobbling mesh, in seconds
+    pub wobble_duration: f32,
+
+    /// How long the mesh takes to burst into bubbles, in seconds
+    pub burst_duration: f32,
+
+    /// How long to show just the bubbles, in seconds
+    pub bubbles_duration: f32,
+
+    /// How long the bubbles take to reform into the mesh, in seconds
+    pub reform_duration: f32,
+
+    /// Whether the model spins at all
+    pub auto_rotate: bool,
+
+    /// How fast the model spins, in radians per second
+    pub rotation_speed: f32,
+}
+
+impl Default for EffectSettings {
+    fn default() -> Self {
+        Self {
+            noise_magnitude: 0.15,
+            noise_scale: 60.0,
+            time_scale: 4.0,
+            dissolve_edge_width: 0.05,
+            dissolve_edge_color: Color::CYAN,
+            bubble_radius: 0.03,
+            bubble_emissive_color: Color::WHITE,
+            bubble_emissive_intensity: 1.0,
+            bubble_color_source: ColorSource::Emissive,
+            bubble_shading: BubbleShading::Emissive,
+            metaballs: false,
+            metaball_blend_radius: 0.02,
+            max_bubbles: 200,
+            sort_bubbles: true,
+            wobble_duration: 1.0,
+            burst_duration: 2.0,
+            bubbles_duration: 1.0,
+            reform_duration: 2.0,
+            auto_rotate: true,
+            rotation_speed: 0.25,
+        }
+    }
+}
+
+/// The stages the effect loops through, in order.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EffectPhase {
+    /// Just the wobbling mesh
+    Wobble,
+    /// The mesh dissolves into bubbles
+    Burst,
+    /// Just the bubbles
+    Bubbles,
+    /// The bubbles turn back into the mesh
+    Reform,
+}
+
+impl EffectSettings {
+    fn phase_durations(&self) -> [(EffectPhase, f32); 4] {
+        [
+            (EffectPhase::Wobble, self.wobble_duration.max(0.0)),
+            (EffectPhase::Burst, self.burst_duration.max(0.0)),
+            (EffectPhase::Bubbles, self.bubbles_duration.max(0.0)),
+            (EffectPhase::Reform, self.reform_duration.max(0.0)),
+        ]
+    }
+
+    /// How long one loop of the effect takes, in seconds.
+    pub fn loop_duration(&self) -> f32 {
+        self.phase_durations()
+            .iter()
+            .map(|(_, duration)| duration)
+            .sum()
+    }
+
+    /// How far into the current loop of the effect we are after `elapsed` seconds.
+    pub fn effect_time(&self, elapsed: f32) -> f32 {
+        let loop_duration = self.loop_duration();
+        if loop_duration <= 0.0 {
+            return 0.0;
+        }
+        elapsed.rem_euclid(loop_duration)
+    }
+
+    /// Which phase the (looping) effect is in after `elapsed` seconds, along with how far
+    /// through that phase it is, from 0 to 1.
+    pub fn phase_at(&self, elapsed: f32) -> (EffectPhase, f32) {
+        if self.loop_duration() <= 0.0 {
+            return (EffectPhase::Wobble, 0.0);
+        }
+
+        let mut time = self.effect_time(elapsed);
+        for (phase, duration) in self.phase_durations() {
+            if time < duration {
+                return (phase, time / duration);
+            }
+            time -= duration;
+        }
+
+        // only reachable through float rounding at the very end of the loop
+        (EffectPhase::Reform, 1.0)
+    }
+
+    /// How far the effect has gone from the mesh (0) to bubbles (1) after `elapsed` seconds.
+    pub fn progress_at(&self, elapsed: f32) -> f32 {
+        match self.phase_at(elapsed) {
+            (EffectPhase::Wobble, _) => 0.0,
+            (EffectPhase::Burst, t) => t,
+            (EffectPhase::Bubbles, _) => 1.0,
+            (EffectPhase::Reform, t) => 1.0 - t,
+        }
+    }
+}
+
+/// How long `Left`/`Right` have to be held before they start repeating, in seconds
+const REPEAT_DELAY: f32 = 0.4;
+
+/// How often a held `Left`/`Right` repeats after that, in seconds
+const REPEAT_INTERVAL: f32 = 0.05;
+
+#[derive(Component, Debug)]
+struct SettingsPanel {
+    /// Index of the currently selected field in [`EffectSettings`]
+    selected: usize,
+    font: Handle<Font>,
+    /// Until `Left`/`Right` repeats, while it's held
+    repeat: Timer,
+}
+
+fn spawn_settings_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(8.0),
+                    left: Val::Px(8.0),
+                    ..default()
+                },
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+            ..default()
+        },
+        SettingsPanel {
+            selected: 0,
+            font: asset_server.load(UI_FONT),
+            repeat: Timer::from_seconds(REPEAT_DELAY, TimerMode::Once),
+        },
+    ));
+}
+
+fn edit_settings(
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mut settings: ResMut<EffectSettings>,
+    mut panels: Query<(&mut SettingsPanel, &mut Visibility)>,
+) {
+    for (mut panel, mut visibility) in &mut panels {
+        if keys.just_pressed(KeyCode::F1) {
+            *visibility = match *visibility {
+                Visibility::Hidden => Visibility::Inherited,
+                _ => Visibility::Hidden,
+            };
+        }
+
+        if *visibility == Visibility::Hidden {
+            continue;
+        }
+
+        let field_count = settings.field_len();
+        if keys.just_pressed(KeyCode::Down) {
+            panel.selected = (panel.selected + 1) % field_count;
+        }
+        if keys.just_pressed(KeyCode::Up) {
+            panel.selected = (panel.selected + field_count - 1) % field_count;
+        }
+
+        let direction = match (keys.pressed(KeyCode::Left), keys.pressed(KeyCode::Right)) {
+            (true, false) => -1,
+            (false, true) => 1,
+            _ => continue,
+        };
+
+        // once per press, then repeatedly while it's held. The timer isn't shown, so it
+        // doesn't count as changing the panel
+        let repeat = &mut panel.bypass_change_detection().repeat;
+        if keys.any_just_pressed([KeyCode::Left, KeyCode::Right]) {
+            *repeat = Timer::from_seconds(REPEAT_DELAY, TimerMode::Once);
+        } else if repeat.tick(time.delta()).just_finished() {
+            repeat.set_duration(Duration::from_secs_f32(REPEAT_INTERVAL));
+            repeat.reset();
+        } else {
+            continue;
+        }
+
+        let fine = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+        let channels = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3].map(|key| keys.pressed(key));
+        let channels = if channels.contains(&true) { channels } else { [true; 3] };
+
+        // only touch the resource when actually editing, so change detection stays useful
+        if let Some(field) = settings.field_at_mut(panel.selected) {
+            adjust_field(field, direction, fine, channels);
+        }
+    }
+}
+
+/// Nudge a numeric field up or down, flip a boolean one, or move an enum on to its next or
+/// previous variant. Floats change proportionally to their size, so the same keys work just as
+/// well for a tiny radius as for a big noise scale. Colors step the selected `channels` (red,
+/// green and blue).
+fn adjust_field(field: &mut dyn Reflect, direction: i32, fine: bool, channels: [bool; 3]) {
+    if let Some(value) = field.downcast_mut::<bool>() {
+        *value = direction > 0;
+    } else if let Some(value) = field.downcast_mut::<f32>() {
+        let step = if fine { 0.002 } else { 0.02 };
+        // with a floor, so values can still get to (and across) zero
+        *value += direction as f32 * step * value.abs().max(0.1);
+    } else if let Some(value) = field.downcast_mut::<u32>() {
+        let step = if fine { 1 } else { 10 };
+        *value = value.saturating_add_signed(direction * step);
+    } else if let Some(color) = field.downcast_mut::<Color>() {
+        let step = direction as f32 * if fine { 0.01 } else { 0.05 };
+        let mut rgba = color.as_rgba_f32();
+        for (channel, _) in rgba.iter_mut().zip(channels).filter(|(_, selected)| *selected) {
+            // no upper limit, since brighter than 1.0 glows with HDR
+            *channel = (*channel + step).max(0.0);
+        }
+        *color = Color::rgba(rgba[0], rgba[1], rgba[2], rgba[3]);
+    } else if let ReflectMut::Enum(value) = field.reflect_mut() {
+        let TypeInfo::Enum(info) = value.get_type_info() else { return };
+        // skipping variants with fields, since there's nothing to fill them in with
+        let count = info.variant_len() as i32;
+        let variant = (1..count)
+            .map(|offset| (value.variant_index() as i32 + direction * offset).rem_euclid(count))
+            .filter_map(|index| info.variant_at(index as usize))
+            .find(|variant| matches!(variant, VariantInfo::Unit(_)));
+        if let Some(variant) = variant {
+            let next = DynamicEnum::new(value.type_name(), variant.name(), DynamicVariant::Unit);
+            value.apply(&next);
+        }
+    }
+}
+
+fn update_settings_panel(
+    settings: Res<EffectSettings>,
+    mut panels: Query<(Ref<SettingsPanel>, &mut Text)>,
+) {
+    for (panel, mut text) in &mut panels {
+        if !settings.is_changed() && !panel.is_changed() {
+            continue;
+        }
+
+        let style = TextStyle {
+            font: panel.font.clone(),
+            font_size: 16.0,
+            ..default()
+        };
+        let selected_style = TextStyle {
+            color: Color::YELLOW,
+            ..style.clone()
+        };
+
+        text.sections = settings
+            .iter_fields()
+            .enumerate()
+            .map(|(i, field)| {
+                let name = settings.name_at(i).unwrap_or_default();
+                let style = if i == panel.selected {
+                    selected_style.clone()
+                } else {
+                    style.clone()
+                };
+                TextSection::new(format!("{name}: {field:?}\n"), style)
+            })
+            .collect();
+    }
+}
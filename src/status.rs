@@ -0,0 +1,98 @@
+//! A line of text at the bottom of the window, for telling the user what happened to something
+//! they did, like a dropped file failing to load. Send a [`StatusMessage`] to show one.
+
+use bevy::prelude::*;
+
+use crate::UI_FONT;
+
+pub struct StatusPlugin;
+
+impl Plugin for StatusPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<StatusMessage>()
+            .add_startup_system(spawn_status_text)
+            .add_system(show_status_messages)
+            .add_system(fade_status_text.after(show_status_messages));
+    }
+}
+
+/// Replaces whatever's in the status text for a few seconds.
+#[derive(Debug, Clone)]
+pub struct StatusMessage {
+    pub text: String,
+    pub color: Color,
+}
+
+impl StatusMessage {
+    pub fn info(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            color: Color::WHITE,
+        }
+    }
+
+    pub fn error(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            color: Color::RED,
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+struct StatusText {
+    timer: Timer,
+    font: Handle<Font>,
+}
+
+/// How long status messages stay on screen, in seconds
+const STATUS_DURATION: f32 = 5.0;
+
+fn spawn_status_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(8.0),
+                    left: Val::Px(8.0),
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        },
+        StatusText {
+            timer: default(),
+            font: asset_server.load(UI_FONT),
+        },
+    ));
+}
+
+fn show_status_messages(
+    mut messages: EventReader<StatusMessage>,
+    mut status_texts: Query<(&mut Text, &mut StatusText)>,
+) {
+    // only the latest one would be visible anyway
+    let Some(message) = messages.iter().last() else { return };
+
+    for (mut text, mut status) in &mut status_texts {
+        *text = Text::from_section(
+            message.text.clone(),
+            TextStyle {
+                font: status.font.clone(),
+                font_size: 16.0,
+                color: message.color,
+            },
+        );
+        status.timer = Timer::from_seconds(STATUS_DURATION, TimerMode::Once);
+    }
+}
+
+fn fade_status_text(time: Res<Time>, mut status: Query<(&mut Text, &mut StatusText)>) {
+    for (mut text, mut status) in &mut status {
+        if status.timer.tick(time.delta()).just_finished() {
+            text.sections.clear();
+        }
+    }
+}
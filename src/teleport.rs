@@ -0,0 +1,138 @@
+use bevy::log;
+use bevy::pbr::{ExtendedMaterial, MaterialPipeline, MaterialPipelineKey};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::mesh::MeshVertexBufferLayout;
+use bevy::render::render_resource::{
+    AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+};
+
+use crate::bubbles::{BubblesPass, BubblesPassPlugin};
+use crate::mesh_storage::{MeshStorage, Vertex};
+use crate::noisy;
+
+/// Draws [`TeleportMaterial`] both as a regular (noisy) mesh, and as the bubbles it bursts into.
+pub struct TeleportMaterialPlugin;
+
+impl Plugin for TeleportMaterialPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(MaterialPlugin::<TeleportMaterial>::default())
+            .add_plugin(BubblesPassPlugin::<TeleportMaterial>::default());
+    }
+}
+
+pub type TeleportMaterial = ExtendedMaterial<Teleport>;
+
+impl MeshStorage for TeleportMaterial {
+    const VERTEX_BINDING: u32 = 101;
+}
+
+impl BubblesPass for TeleportMaterial {
+    const VERTEX_ENTRY_POINT: &'static str = "bubble_vertex";
+    const FRAGMENT_ENTRY_POINT: &'static str = "bubble_fragment";
+
+    fn bubble_radius(&self) -> f32 {
+        // once fully grown
+        self.extended.bubble_radius
+    }
+
+    fn prepass_shader() -> ShaderRef {
+        "shaders/teleport_prepass.wgsl".into()
+    }
+}
+
+/// The whole teleport effect in one material: the mesh wobbles with noise and dissolves
+/// away, while bubbles grow out of the triangles it leaves behind.
+#[derive(AsBindGroup, TypeUuid, Reflect, Debug, Clone)]
+#[uuid = "0b0ae5a5-4a70-4b1c-9a51-e35f8e1f2f3c"]
+pub struct Teleport {
+    /// How big the bubbles should be, once fully grown
+    #[uniform(100)]
+    pub bubble_radius: f32,
+
+    /// A binding to reuse the vertex buffer as storage, for the bubbles.
+    #[storage(101, read_only)]
+    #[reflect(ignore)]
+    pub mesh_vertex_buffer: Vec<Vertex>,
+
+    /// How far (at most) offset vertices should be
+    #[uniform(102)]
+    pub noise_magnitude: f32,
+
+    /// The scale of the noise, used for both the displacement and the dissolve
+    #[uniform(103)]
+    pub noise_scale: f32,
+
+    /// The speed at which the displacement should animate
+    #[uniform(104)]
+    pub time_scale: f32,
+
+    /// How far along the crossfade is, from 0 (just the mesh) to 1 (just bubbles)
+    #[uniform(105)]
+    pub progress: f32,
+
+    /// Tints the emissive texture the bubbles are colored with
+    #[uniform(106)]
+    pub emissive_color: Color,
+
+    /// Multiplies the bubbles' emissive color, see [`Bubbles::emissive_intensity`]
+    ///
+    /// [`Bubbles::emissive_intensity`]: crate::bubbles::Bubbles::emissive_intensity
+    #[uniform(107)]
+    pub emissive_intensity: f32,
+
+    /// Seconds since startup, see [`NoisyVerts::time`](crate::noisy::NoisyVerts::time)
+    #[uniform(108)]
+    pub time: f32,
+}
+
+impl Default for Teleport {
+    fn default() -> Self {
+        Self {
+            bubble_radius: 1.0,
+            mesh_vertex_buffer: Vec::new(),
+            noise_magnitude: 1.0,
+            noise_scale: 1.0,
+            time_scale: 1.0,
+            progress: 0.0,
+            emissive_color: Color::WHITE,
+            emissive_intensity: 1.0,
+            time: 0.0,
+        }
+    }
+}
+
+impl Material for Teleport {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/teleport.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/teleport.wgsl".into()
+    }
+
+    fn prepass_vertex_shader() -> ShaderRef {
+        "shaders/teleport_mesh_prepass.wgsl".into()
+    }
+
+    fn prepass_fragment_shader() -> ShaderRef {
+        "shaders/teleport_mesh_prepass.wgsl".into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        noisy::specialize_prepass(descriptor, layout)?;
+
+        if let Some(label) = &mut descriptor.label {
+            *label = format!("teleport_{label}").into();
+        }
+
+        log::debug!("vert buffers: {:#?}", descriptor.vertex.buffers);
+
+        Ok(())
+    }
+}